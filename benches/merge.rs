@@ -0,0 +1,85 @@
+//! Merges of overlapping runs where most values are superseded: the case
+//! `StoreFile::peek_key` is for, as `merge` picks each winner from the keys
+//! alone and never reads the values that lose. Run with
+//! `cargo bench --bench merge`.
+
+use std::time::Instant;
+
+use yalskv::util::data;
+use yalskv::{kv, CompactionStrategy, Store, StoreOptions};
+
+const N: usize = 20_000;
+const ROUNDS: usize = 8;
+const VAL_LEN: usize = 512;
+/// Large enough for a round to flush to a single run.
+const RUN_BYTES: u64 = 64 * 1024 * 1024;
+
+fn main() -> kv::Result<()> {
+    println!("N={N} rounds={ROUNDS} val={VAL_LEN}");
+    let keys: Vec<Vec<u8>> = data(N, 42).into_iter().map(|(key, _)| key).collect();
+
+    // One log, split into chunks that overlap and merged into one file.
+    let options = StoreOptions::new().truncate(true);
+    let mut store = Store::open_or_create_with("target/bench-merge-full", options)?;
+    fill(&mut store, &keys, |_| Ok(()))?;
+    let now = Instant::now();
+    let result = store.reduce(4 * 1024 * 1024)?;
+    report("reduce", now, result.input_records, result.input_bytes);
+
+    // Level-0 runs, one per round, merged down together.
+    let options = StoreOptions::new()
+        .truncate(true)
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(RUN_BYTES)
+        .level0_runs(ROUNDS + 1);
+    let mut store = Store::open_or_create_with("target/bench-merge-leveled", options)?;
+    fill(&mut store, &keys, |store| store.compact())?;
+    let input: u64 = store
+        .file_stats()?
+        .iter()
+        .map(|stat| stat.total_bytes)
+        .sum();
+    let mut store = reopen_leveled(store)?;
+    let now = Instant::now();
+    store.compact()?;
+    report("leveled", now, N * ROUNDS, input);
+
+    if store.len() != N {
+        eprintln!("!len: {} keys, expected {N}", store.len());
+    }
+    Ok(())
+}
+
+fn fill(
+    store: &mut Store,
+    keys: &[Vec<u8>],
+    mut after_round: impl FnMut(&mut Store) -> kv::Result<()>,
+) -> kv::Result<()> {
+    for round in 0..ROUNDS {
+        let pairs = keys
+            .iter()
+            .map(|key| (key.clone(), vec![round as u8; VAL_LEN]));
+        store.extend(pairs)?;
+        after_round(store)?;
+    }
+    Ok(())
+}
+
+/// Opens the leveled store again with a `level0_runs` its runs now exceed,
+/// so that the next `compact` merges all of them down at once.
+fn reopen_leveled(store: Store) -> kv::Result<Store> {
+    let base = store.base_dir().to_string_lossy().into_owned();
+    drop(store);
+    let options = StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(RUN_BYTES)
+        .level0_runs(ROUNDS);
+    Store::open_with(&base, options)
+}
+
+fn report(name: &str, started: Instant, records: usize, bytes: u64) {
+    let ms = (started.elapsed().as_millis() as usize).max(1);
+    let op = records * 1000 / ms;
+    let kb = bytes as usize * 1000 / ms / 1024;
+    println!("{name}: ok (ms={ms} op={op} kb={kb})");
+}
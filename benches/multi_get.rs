@@ -0,0 +1,58 @@
+//! Serial `multi_get` against `par_multi_get` over the same batches of keys.
+//! Run with `cargo bench --bench multi_get --features rayon`; without the
+//! feature only the serial path is measured.
+
+use std::time::Instant;
+
+use yalskv::util::{data, mix};
+use yalskv::{kv, Store, StoreOptions};
+
+const N: usize = 200_000;
+const BATCH: usize = 10_000;
+
+fn main() -> kv::Result<()> {
+    let options = StoreOptions::new().truncate(true);
+    let mut store = Store::open_or_create_with("target/bench-multi-get", options)?;
+    let data = data(N, 42);
+    store.extend(data.iter().cloned())?;
+    store.reduce(32 * 1024 * 1024)?;
+
+    let data = mix(data, 1);
+    let batches: Vec<Vec<&[u8]>> = data
+        .chunks(BATCH)
+        .map(|chunk| chunk.iter().map(|(key, _)| key.as_slice()).collect())
+        .collect();
+    println!("N={N} batch={BATCH}");
+
+    let now = Instant::now();
+    let mut serial = Vec::with_capacity(batches.len());
+    for keys in batches.iter() {
+        serial.push(store.multi_get(keys)?);
+    }
+    report("multi_get", now);
+
+    #[cfg(feature = "rayon")]
+    {
+        let now = Instant::now();
+        let mut parallel = Vec::with_capacity(batches.len());
+        for keys in batches.iter() {
+            parallel.push(store.par_multi_get(keys)?);
+        }
+        report("par_multi_get", now);
+        if parallel != serial {
+            eprintln!("!match: par_multi_get differs from multi_get");
+        }
+    }
+
+    let missing = serial.iter().flatten().filter(|val| val.is_none()).count();
+    if missing > 0 {
+        eprintln!("!found: {missing} keys");
+    }
+    Ok(())
+}
+
+fn report(name: &str, started: Instant) {
+    let ms = (started.elapsed().as_millis() as usize).max(1);
+    let op = N * 1000 / ms;
+    println!("{name}: ok (ms={ms} op={op})");
+}
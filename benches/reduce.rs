@@ -0,0 +1,58 @@
+//! `reduce` of an active file that fits in one chunk, sorted in memory and
+//! written out directly, against the same file cut in two and merged back
+//! through the split directory. Run with `cargo bench --bench reduce`.
+
+use std::time::Instant;
+
+use yalskv::util::data;
+use yalskv::{kv, Store, StoreOptions};
+
+const N: usize = 100_000;
+const VAL_LEN: usize = 256;
+
+fn main() -> kv::Result<()> {
+    println!("N={N} val={VAL_LEN}");
+    let pairs: Vec<(Vec<u8>, Vec<u8>)> = data(N, 42)
+        .into_iter()
+        .map(|(key, _)| (key, vec![0u8; VAL_LEN]))
+        .collect();
+
+    let mut single = filled("target/bench-reduce-single", &pairs)?;
+    let input: u64 = single
+        .file_stats()?
+        .iter()
+        .map(|stat| stat.total_bytes)
+        .sum();
+    let now = Instant::now();
+    let result = single.reduce(input as usize + 1)?;
+    report("single", now, result.input_records, result.input_bytes);
+
+    let mut split = filled("target/bench-reduce-split", &pairs)?;
+    let now = Instant::now();
+    let result = split.reduce(input as usize / 2)?;
+    report("split", now, result.input_records, result.input_bytes);
+
+    // The split writes each chunk out and the merge reads it back.
+    println!("avoided: kb={}", 2 * input / 1024);
+    if single.len() != N || split.len() != N {
+        eprintln!(
+            "!len: {} and {} keys, expected {N}",
+            single.len(),
+            split.len()
+        );
+    }
+    Ok(())
+}
+
+fn filled(path: &str, pairs: &[(Vec<u8>, Vec<u8>)]) -> kv::Result<Store> {
+    let mut store = Store::open_or_create_with(path, StoreOptions::new().truncate(true))?;
+    store.extend(pairs.iter().cloned())?;
+    Ok(store)
+}
+
+fn report(name: &str, started: Instant, records: usize, bytes: u64) {
+    let ms = (started.elapsed().as_millis() as usize).max(1);
+    let op = records * 1000 / ms;
+    let kb = bytes as usize * 1000 / ms / 1024;
+    println!("{name}: ok (ms={ms} op={op} kb={kb})");
+}
@@ -4,8 +4,7 @@ use yalskv::{kv, Store};
 use std::time::SystemTime;
 
 fn main() -> kv::Result<()> {
-    std::fs::create_dir_all("target/db")?;
-    let mut store = Store::open("target/db")?;
+    let mut store = Store::open_or_create("target/db")?;
 
     const N: usize = 1000000;
     let limit = 1024 * 1024 * 32;
@@ -32,15 +31,15 @@ fn main() -> kv::Result<()> {
     let data = mix(data, 1);
     now = SystemTime::now();
     let mut found = Vec::with_capacity(data.len());
-    for (key, _) in data.iter() {        
-        let val = store.lookup(key)?.unwrap_or_default();
+    for (key, _) in data.iter() {
+        let val = store.get(key)?.unwrap_or_default();
         found.push(val);
     }
     let ms = (now.elapsed().unwrap().as_millis() as usize).max(1);
     let op = N * 1000 / ms;
     let kb = N * 1000 * (64 + 64 + 3 * 8) / ms / 1024;
     println!("lookup: ok (ms={ms} op={op} kb={kb})");
-    for ((key, val), res) in data.iter().zip(found.iter()) {        
+    for ((key, val), res) in data.iter().zip(found.iter()) {
         if res.is_empty() {
             eprintln!("!found: key={}", hex(key));
         } else if val != res {
@@ -49,37 +48,30 @@ fn main() -> kv::Result<()> {
     }
 
     now = SystemTime::now();
-    store.file().reset()?;
     let mut found = Vec::with_capacity(data.len());
-    for rec in store.file() {
-        found.push(rec.key().to_vec());
+    for rec in store.sorted_records() {
+        found.push(rec?.key().to_vec());
     }
     let ms = (now.elapsed().unwrap().as_millis() as usize).max(1);
     let op = N * 1000 / ms;
     let kb = N * 1000 * (64 + 64 + 3 * 8) / ms / 1024;
     println!("sorted: ok (ms={ms} op={op} kb={kb})");
 
-    let mut prev: Vec<u8> = Default::default();
+    let mut prev: Option<Vec<u8>> = None;
     for (i, next) in found.into_iter().enumerate() {
-        if prev.is_empty() {
-            prev = next;
-            continue;
-        }
-
-        if prev > next {
+        if let Some(prev) = prev.as_ref().filter(|prev| *prev > &next) {
             println!(
                 "!sorted (i={}):\n\tprev={}\n\tnext={}",
                 i,
-                hex(&prev),
+                hex(prev),
                 hex(&next)
             );
         }
-        prev = next;
+        prev = Some(next);
     }
 
     let data = mix(data, 2);
     now = SystemTime::now();
-    store.file().unset()?;
     for (key, _) in data.iter() {
         if !store.remove(key)? {
             eprintln!("!exist: key={}", hex(key));
@@ -97,8 +89,7 @@ fn main() -> kv::Result<()> {
     let kb = N * 1000 * (64 + 64 + 3 * 8) / ms / 1024;
     println!("reduce: ok (ms={ms} op={op} kb={kb})");
 
-    store.file().reset()?;
-    let count = store.file().count();
+    let count = store.file().record_count()?;
     if count > 0 {
         eprintln!("!empty: {}", count);
     }
@@ -0,0 +1,141 @@
+//! `INDEX` is a checkpoint of the in-memory index, so that `open` does not
+//! have to replay every data file. It records the exact set of files (id,
+//! length and a CRC32 of the tail) it was taken against and is trailed by a
+//! CRC32 of its contents; a checkpoint that fails either check is ignored
+//! and the index is rebuilt.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::prelude::FileExt;
+use std::path::Path;
+
+use crate::manifest::{put_bytes, put_u64, write_atomic, Reader};
+use crate::util::crc32;
+use crate::{FileId, Index, RecordLocation};
+
+pub(crate) const NAME: &str = "INDEX";
+
+/// How many bytes at the end of a data file `FileMark::tail` covers.
+const TAIL_BYTES: u64 = 4096;
+
+/// A data file as a checkpoint knows it. The tail catches a file rewritten
+/// to its old length, which compaction in place can do.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct FileMark {
+    pub(crate) id: FileId,
+    pub(crate) len: u64,
+    pub(crate) tail: u32,
+}
+
+impl FileMark {
+    pub(crate) fn of(id: FileId, file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        let at = len.saturating_sub(TAIL_BYTES);
+        let mut tail = vec![0u8; (len - at) as usize];
+        file.read_exact_at(&mut tail, at)?;
+        Ok(Self {
+            id,
+            len,
+            tail: crc32(&tail),
+        })
+    }
+
+    /// The mark of a file that does not exist yet.
+    pub(crate) fn empty(id: FileId) -> Self {
+        Self {
+            id,
+            len: 0,
+            tail: crc32(&[]),
+        }
+    }
+}
+
+pub(crate) struct Checkpoint {
+    pub(crate) index: Index,
+    pub(crate) dead_bytes: u64,
+}
+
+pub(crate) fn write(
+    path: &Path,
+    files: &[FileMark],
+    index: &Index,
+    dead_bytes: u64,
+) -> io::Result<()> {
+    let mut dst = Vec::new();
+    put_u64(&mut dst, files.len() as u64);
+    for file in files {
+        put_u64(&mut dst, file.id.0);
+        put_u64(&mut dst, file.len);
+        put_u64(&mut dst, file.tail as u64);
+    }
+    put_u64(&mut dst, dead_bytes);
+    put_u64(&mut dst, index.len() as u64);
+    for (key, entry) in index.iter() {
+        put_bytes(&mut dst, key);
+        put_u64(&mut dst, entry.file.0);
+        put_u64(&mut dst, entry.offset);
+        put_u64(&mut dst, entry.length);
+    }
+    let crc = crc32(&dst);
+    dst.extend_from_slice(&crc.to_be_bytes());
+    write_atomic(path, &dst)
+}
+
+/// Loads the checkpoint only if it is intact and was taken against exactly
+/// `files`; `None` means the caller has to rebuild the index.
+pub(crate) fn read(path: &Path, files: &[FileMark]) -> io::Result<Option<Checkpoint>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if bytes.len() < 4 {
+        return Ok(None);
+    }
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    if crc32(body).to_be_bytes() != crc {
+        return Ok(None);
+    }
+    Ok(decode(body, files).ok().flatten())
+}
+
+fn decode(body: &[u8], files: &[FileMark]) -> io::Result<Option<Checkpoint>> {
+    let mut src = Reader::new(body);
+    if src.u64()? != files.len() as u64 {
+        return Ok(None);
+    }
+    for file in files {
+        let found = FileMark {
+            id: FileId(src.u64()?),
+            len: src.u64()?,
+            tail: src.u64()? as u32,
+        };
+        if found != *file {
+            return Ok(None);
+        }
+    }
+    let dead_bytes = src.u64()?;
+    let mut index = Index::new();
+    for _ in 0..src.u64()? {
+        let key = src.bytes()?.to_vec();
+        let entry = RecordLocation {
+            file: FileId(src.u64()?),
+            offset: src.u64()?,
+            length: src.u64()?,
+        };
+        // An end past u64::MAX is as corrupt as one past the end of the file.
+        let fits = entry.offset.checked_add(entry.length).is_some_and(|end| {
+            files
+                .iter()
+                .any(|file| file.id == entry.file && end <= file.len)
+        });
+        if !fits {
+            return Ok(None);
+        }
+        index.insert(key, entry);
+    }
+    if !src.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Checkpoint { index, dead_bytes }))
+}
@@ -0,0 +1,114 @@
+//! Value encryption with AES-256-GCM. A sealed value is a random 12-byte
+//! nonce, the ciphertext and the 16-byte tag; the record key is bound in as
+//! associated data, so a value cannot be moved to another key unnoticed.
+//! Keys themselves stay in the clear, as the index and files are ordered
+//! by them.
+
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+#[cfg(feature = "encryption")]
+use aes_gcm::{Aes256Gcm, Nonce};
+#[cfg(feature = "encryption")]
+use rand::RngCore;
+
+/// Sealed into the manifest to tell, on open, whether a key is the one the
+/// store was written with.
+#[cfg(feature = "encryption")]
+const KEY_CHECK: &[u8] = b"yalskv key check";
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "encryption")]
+const TAG_LEN: usize = 16;
+
+/// A 256-bit encryption key; `Debug` leaves the key material out.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct EncryptionKey(pub [u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub(crate) struct Cipher(Aes256Gcm);
+
+#[cfg(feature = "encryption")]
+impl Cipher {
+    pub(crate) fn new(key: &EncryptionKey) -> Self {
+        Self(Aes256Gcm::new(&key.0.into()))
+    }
+
+    /// The bytes a sealed value takes beyond the plaintext.
+    pub(crate) fn overhead(&self) -> usize {
+        NONCE_LEN + TAG_LEN
+    }
+
+    pub(crate) fn seal(&self, key: &[u8], val: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut buffer = Vec::with_capacity(NONCE_LEN + val.len() + TAG_LEN);
+        buffer.extend_from_slice(&nonce);
+        buffer.extend_from_slice(val);
+        let tag = self
+            .0
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), key, &mut buffer[NONCE_LEN..])
+            .expect("value too long to encrypt");
+        buffer.extend_from_slice(&tag);
+        buffer
+    }
+
+    /// A value only this key opens to `KEY_CHECK`, for the manifest.
+    pub(crate) fn key_check(&self) -> Vec<u8> {
+        self.seal(KEY_CHECK, KEY_CHECK)
+    }
+
+    /// Whether `check` came from `key_check` under this key.
+    pub(crate) fn checks(&self, check: &[u8]) -> bool {
+        let mut buffer = check.to_vec();
+        self.open(KEY_CHECK, &mut buffer) && buffer == KEY_CHECK
+    }
+
+    /// Decrypts `buffer` in place; `false` if it fails authentication.
+    pub(crate) fn open(&self, key: &[u8], buffer: &mut Vec<u8>) -> bool {
+        if buffer.len() < NONCE_LEN + TAG_LEN {
+            return false;
+        }
+        let nonce: [u8; NONCE_LEN] = buffer[..NONCE_LEN].try_into().unwrap();
+        buffer.drain(..NONCE_LEN);
+        self.0
+            .decrypt_in_place(Nonce::from_slice(&nonce), key, buffer)
+            .is_ok()
+    }
+}
+
+/// Without the `encryption` feature there is no way to configure a key, so
+/// a store never holds a cipher.
+#[cfg(not(feature = "encryption"))]
+pub(crate) enum Cipher {}
+
+#[cfg(not(feature = "encryption"))]
+impl Cipher {
+    pub(crate) fn overhead(&self) -> usize {
+        match *self {}
+    }
+
+    pub(crate) fn seal(&self, _: &[u8], _: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    pub(crate) fn key_check(&self) -> Vec<u8> {
+        match *self {}
+    }
+
+    pub(crate) fn checks(&self, _: &[u8]) -> bool {
+        match *self {}
+    }
+
+    pub(crate) fn open(&self, _: &[u8], _: &mut Vec<u8>) -> bool {
+        match *self {}
+    }
+}
@@ -0,0 +1,196 @@
+//! On-disk record layout.
+//!
+//! A data file starts with a file header followed by a plain sequence of
+//! records. Every integer field of a record is a `u64` in the byte order
+//! declared by the file header, and the key and value bytes follow the record
+//! header as-is:
+//!
+//! ```text
+//! file:   | MAGIC | VERSION | order | reserved (2) | records... |
+//! insert: | op = INSERT | key_len | val_len | key | val |
+//! remove: | op = REMOVE | key_len | key |
+//! range:  | op = REMOVE_RANGE | start_len | end_len | start | end |
+//! ```
+//!
+//! Files of `VARINT_VERSION` encode the op code and lengths as LEB128
+//! varints instead, and their order byte is zero. Range tombstones only
+//! appear in files of `RANGE_VERSION`, whose order byte also tells varint
+//! files apart (0 big, 1 little, 2 varint), so older readers reject them
+//! rather than misread them. Files without the header predate it and are
+//! read as big-endian records starting at offset zero.
+
+use std::io;
+
+pub const INSERT: u64 = 1;
+pub const REMOVE: u64 = 2;
+pub const REMOVE_RANGE: u64 = 3;
+
+/// Width of each header field (op code and lengths).
+pub const FIELD_LEN: usize = std::mem::size_of::<u64>();
+
+pub const INSERT_HEADER_LEN: usize = 3 * FIELD_LEN;
+pub const REMOVE_HEADER_LEN: usize = 2 * FIELD_LEN;
+
+pub const fn insert_len(key_len: usize, val_len: usize) -> usize {
+    INSERT_HEADER_LEN + key_len + val_len
+}
+
+pub const fn remove_len(key_len: usize) -> usize {
+    REMOVE_HEADER_LEN + key_len
+}
+
+pub const MAGIC: [u8; 4] = *b"YLKV";
+pub const VERSION: u8 = 1;
+pub const VARINT_VERSION: u8 = 2;
+pub const RANGE_VERSION: u8 = 3;
+pub const FILE_HEADER_LEN: usize = 8;
+
+pub const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    pub fn encode(self, val: u64) -> [u8; FIELD_LEN] {
+        match self {
+            ByteOrder::Big => val.to_be_bytes(),
+            ByteOrder::Little => val.to_le_bytes(),
+        }
+    }
+
+    pub fn decode(self, bytes: [u8; FIELD_LEN]) -> u64 {
+        match self {
+            ByteOrder::Big => u64::from_be_bytes(bytes),
+            ByteOrder::Little => u64::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// How a file encodes the op code and lengths of its records.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// `u64` fields of `FIELD_LEN` bytes in the given byte order (`VERSION`).
+    Fixed(ByteOrder),
+    /// LEB128 varints (`VARINT_VERSION`).
+    Varint,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Fixed(ByteOrder::Big)
+    }
+}
+
+impl Encoding {
+    pub fn field_len(self, val: u64) -> usize {
+        match self {
+            Encoding::Fixed(_) => FIELD_LEN,
+            Encoding::Varint => (64 - (val | 1).leading_zeros() as usize).div_ceil(7),
+        }
+    }
+
+    pub fn put(self, dst: &mut Vec<u8>, mut val: u64) {
+        match self {
+            Encoding::Fixed(order) => dst.extend_from_slice(&order.encode(val)),
+            Encoding::Varint => {
+                while val >= 0x80 {
+                    dst.push(val as u8 | 0x80);
+                    val >>= 7;
+                }
+                dst.push(val as u8);
+            }
+        }
+    }
+
+    /// Decodes the field at the start of `src` and returns it with its width,
+    /// or `None` if `src` ends first or holds no valid field.
+    pub fn get(self, src: &[u8]) -> Option<(u64, usize)> {
+        match self {
+            Encoding::Fixed(order) => {
+                let bytes = src.get(..FIELD_LEN)?;
+                Some((order.decode(bytes.try_into().unwrap()), FIELD_LEN))
+            }
+            Encoding::Varint => {
+                let mut val = 0u64;
+                for (i, byte) in src.iter().take(MAX_VARINT_LEN).enumerate() {
+                    let bits = (*byte & 0x7F) as u64;
+                    if i == MAX_VARINT_LEN - 1 && bits > 1 {
+                        return None;
+                    }
+                    val |= bits << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return Some((val, i + 1));
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    pub fn insert_len(self, key_len: usize, val_len: usize) -> usize {
+        self.insert_header_len(key_len, val_len) + key_len + val_len
+    }
+
+    /// The op code and lengths of an insert, which the key and value follow.
+    pub fn insert_header_len(self, key_len: usize, val_len: usize) -> usize {
+        self.field_len(INSERT) + self.field_len(key_len as u64) + self.field_len(val_len as u64)
+    }
+
+    pub fn remove_len(self, key_len: usize) -> usize {
+        self.field_len(REMOVE) + self.field_len(key_len as u64) + key_len
+    }
+
+    pub fn remove_range_len(self, start_len: usize, end_len: usize) -> usize {
+        self.field_len(REMOVE_RANGE)
+            + self.field_len(start_len as u64)
+            + self.field_len(end_len as u64)
+            + start_len
+            + end_len
+    }
+
+    /// Upper bound on the op code and lengths of a record.
+    pub fn max_header_len(self) -> usize {
+        match self {
+            Encoding::Fixed(_) => INSERT_HEADER_LEN,
+            Encoding::Varint => 3 * MAX_VARINT_LEN,
+        }
+    }
+}
+
+/// The header of a file in `encoding`; `ranges` if it may hold range
+/// tombstones.
+pub fn file_header(encoding: Encoding, ranges: bool) -> [u8; FILE_HEADER_LEN] {
+    let mut header = [0u8; FILE_HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    (header[4], header[5]) = match (encoding, ranges) {
+        (Encoding::Fixed(ByteOrder::Big), false) => (VERSION, 0),
+        (Encoding::Fixed(ByteOrder::Little), false) => (VERSION, 1),
+        (Encoding::Varint, false) => (VARINT_VERSION, 0),
+        (Encoding::Fixed(ByteOrder::Big), true) => (RANGE_VERSION, 0),
+        (Encoding::Fixed(ByteOrder::Little), true) => (RANGE_VERSION, 1),
+        (Encoding::Varint, true) => (RANGE_VERSION, 2),
+    };
+    header
+}
+
+/// Returns the encoding declared by a file header and whether the file may
+/// hold range tombstones, `None` if `header` is not one (a headerless file),
+/// or an error for an unsupported header.
+pub fn parse_file_header(header: &[u8; FILE_HEADER_LEN]) -> io::Result<Option<(Encoding, bool)>> {
+    if header[..4] != MAGIC {
+        return Ok(None);
+    }
+    match (header[4], header[5]) {
+        (VERSION, 0) => Ok(Some((Encoding::Fixed(ByteOrder::Big), false))),
+        (VERSION, 1) => Ok(Some((Encoding::Fixed(ByteOrder::Little), false))),
+        (VARINT_VERSION, 0) => Ok(Some((Encoding::Varint, false))),
+        (RANGE_VERSION, 0) => Ok(Some((Encoding::Fixed(ByteOrder::Big), true))),
+        (RANGE_VERSION, 1) => Ok(Some((Encoding::Fixed(ByteOrder::Little), true))),
+        (RANGE_VERSION, 2) => Ok(Some((Encoding::Varint, true))),
+        _ => Err(io::Error::from(io::ErrorKind::Unsupported)),
+    }
+}
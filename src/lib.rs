@@ -1,319 +1,3494 @@
+use std::collections::{btree_map, BTreeSet, BinaryHeap};
 use std::fs::OpenOptions;
-use std::io::{self, Write};
-use std::io::{Seek, SeekFrom};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
 use std::os::unix::prelude::FileExt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{collections::BTreeMap, fs::File};
 
+mod checkpoint;
+mod crypto;
+pub mod format;
+mod logging;
+mod manifest;
+mod order;
+pub mod sharded;
+mod telemetry;
+pub mod typed;
 pub mod util;
 
+use checkpoint::FileMark;
+use crypto::Cipher;
+#[cfg(feature = "encryption")]
+pub use crypto::EncryptionKey;
+use logging::{error, info, warn};
+use manifest::{Manifest, RunMeta};
+pub use order::{Comparator, CompareFn};
+use order::{Head, OrderedKeys, OrderedRange};
+use telemetry::Timer;
+
+use format::{ByteOrder, Encoding, FIELD_LEN, FILE_HEADER_LEN, INSERT, REMOVE, REMOVE_RANGE};
+
 pub mod kv {
 
-    #[derive(Debug)]
-    pub enum Error {
-        IO(std::io::Error),
-        Unknown(String),
+    #[derive(Debug)]
+    pub enum Error {
+        IO(std::io::Error),
+        Corrupt { file: u64, offset: u64 },
+        Unknown(String),
+    }
+
+    impl From<std::io::Error> for Error {
+        fn from(e: std::io::Error) -> Self {
+            Self::IO(e)
+        }
+    }
+
+    pub type Result<T> = std::result::Result<T, Error>;
+}
+
+/// Where a value is stored: the data file and the byte range of the value
+/// within it. This is what the index maps each live key to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RecordLocation {
+    file: FileId,
+    offset: u64,
+    length: u64,
+}
+
+impl RecordLocation {
+    pub fn new(file: FileId, offset: u64, length: u64) -> Self {
+        Self {
+            file,
+            offset,
+            length,
+        }
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CompactionStrategy {
+    Full,
+    Leveled,
+    SizeTiered,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoreOptions {
+    compaction: CompactionStrategy,
+    run_bytes: u64,
+    level0_runs: usize,
+    level_fanout: u64,
+    tier_min_runs: usize,
+    byte_order: ByteOrder,
+    varint_lengths: bool,
+    write_buffer_bytes: usize,
+    truncate: bool,
+    skip_identical_writes: bool,
+    verify_on_read: bool,
+    skip_missing_files: bool,
+    timestamps: bool,
+    compaction_rate_limit: Option<u64>,
+    compact_dead_ratio: f64,
+    compact_max_files: usize,
+    comparator: Comparator,
+    names: FileNames,
+    #[cfg(feature = "encryption")]
+    encryption_key: Option<EncryptionKey>,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            compaction: CompactionStrategy::Full,
+            run_bytes: 32 * 1024 * 1024,
+            level0_runs: 4,
+            level_fanout: 10,
+            tier_min_runs: 4,
+            byte_order: ByteOrder::Big,
+            varint_lengths: false,
+            write_buffer_bytes: WRITE_BUFFER_BYTES,
+            truncate: false,
+            skip_identical_writes: false,
+            verify_on_read: false,
+            skip_missing_files: false,
+            timestamps: false,
+            compaction_rate_limit: None,
+            compact_dead_ratio: 0.5,
+            compact_max_files: 64,
+            comparator: Comparator::default(),
+            names: FileNames::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+        }
+    }
+}
+
+impl StoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compaction(mut self, strategy: CompactionStrategy) -> Self {
+        self.compaction = strategy;
+        self
+    }
+
+    /// Size at which compaction cuts a new sorted run. Must not be 0.
+    pub fn run_bytes(mut self, bytes: u64) -> Self {
+        self.run_bytes = bytes;
+        self
+    }
+
+    /// Runs the first level collects before leveled compaction merges them
+    /// down. Must not be 0.
+    pub fn level0_runs(mut self, runs: usize) -> Self {
+        self.level0_runs = runs;
+        self
+    }
+
+    /// How many times larger each level is than the one above it. Must be
+    /// at least 2: with less, a level never outgrows the one above it and
+    /// compaction would keep pushing runs down.
+    pub fn level_fanout(mut self, fanout: u64) -> Self {
+        self.level_fanout = fanout;
+        self
+    }
+
+    pub fn tier_min_runs(mut self, runs: usize) -> Self {
+        self.tier_min_runs = runs;
+        self
+    }
+
+    /// Pace the bytes compaction writes to about `bytes_per_sec`, leaving
+    /// disk bandwidth to foreground reads and writes.
+    ///
+    /// There is no setting for how many compactions run at once, as there is
+    /// nothing for it to limit: every compaction that changes the layout,
+    /// leveled ones included, takes `&mut Store` and so runs alone. Only
+    /// `prepare_reduce` runs next to other work, and as just one pending
+    /// reduce can be finished, callers sharing a store should serialize it.
+    pub fn compaction_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.compaction_rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Share of dead bytes on disk past which `compact_if_needed` compacts
+    /// and `doctor` advises it.
+    pub fn compact_dead_ratio(mut self, ratio: f64) -> Self {
+        self.compact_dead_ratio = ratio;
+        self
+    }
+
+    /// Number of data files past which `compact_if_needed` compacts.
+    pub fn compact_max_files(mut self, files: usize) -> Self {
+        self.compact_max_files = files;
+        self
+    }
+
+    /// Sort keys by `cmp` instead of by their bytes, in scans and in the
+    /// files compaction writes. Fixed once the store holds data: opening it
+    /// with a comparator of another `name` fails.
+    pub fn comparator(mut self, name: &str, cmp: Box<CompareFn>) -> Self {
+        self.comparator = Comparator::new(name, cmp);
+        self
+    }
+
+    /// Prepended to the name of every file of the store, the manifest and
+    /// the index checkpoint included, so that several stores can share a
+    /// directory. Must not contain a path separator.
+    pub fn file_prefix(mut self, prefix: &str) -> Self {
+        self.names.prefix = prefix.to_string();
+        self
+    }
+
+    /// Extension of the data files, `dat` by default. Must not be `tmp` or
+    /// `reduce`, which name files being written.
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.names.extension = extension.to_string();
+        self
+    }
+
+    pub fn byte_order(mut self, order: ByteOrder) -> Self {
+        self.byte_order = order;
+        self
+    }
+
+    /// Write new files with varint-encoded lengths (`format::VARINT_VERSION`),
+    /// which shrinks records with short keys and values. Files already on
+    /// disk keep their encoding until compaction rewrites them.
+    pub fn varint_lengths(mut self, varint: bool) -> Self {
+        self.varint_lengths = varint;
+        self
+    }
+
+    /// How many bytes of records the active file buffers before writing them
+    /// out. `Store::insert` and `remove` still write through on every call;
+    /// this bounds the buffer for `extend` and other bulk writes, trading
+    /// syscalls for what a crash before the next flush loses.
+    pub fn write_buffer_bytes(mut self, bytes: usize) -> Self {
+        self.write_buffer_bytes = bytes;
+        self
+    }
+
+    fn is_valid(&self) -> bool {
+        self.names.is_valid() && self.run_bytes > 0 && self.level0_runs > 0 && self.level_fanout > 1
+    }
+
+    fn encoding(&self) -> Encoding {
+        if self.varint_lengths {
+            Encoding::Varint
+        } else {
+            Encoding::Fixed(self.byte_order)
+        }
+    }
+
+    /// Discard any data already present in the directory when opening.
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Skip inserts of a value equal to the one already stored, at the cost
+    /// of reading the stored value on every insert.
+    pub fn skip_identical_writes(mut self, skip: bool) -> Self {
+        self.skip_identical_writes = skip;
+        self
+    }
+
+    /// Before reading a value, decode the header and key of the record it is
+    /// in and check that they match the index entry, turning a stale or
+    /// corrupt entry into a `Corrupt` error instead of someone else's bytes.
+    pub fn verify_on_read(mut self, verify: bool) -> Self {
+        self.verify_on_read = verify;
+        self
+    }
+
+    /// Open a store even if a sorted run in its manifest has lost its data
+    /// file, dropping the run (with a warning) instead of failing with
+    /// `Corrupt`. Reads of keys in the run's range then fail with `Corrupt`
+    /// rather than return an older value it overwrote, until the key is
+    /// written again. That lasts as long as the store stays open: the run is
+    /// out of the manifest from then on. A missing active log is treated the
+    /// same way, except that with no range known its writes are just lost.
+    pub fn skip_missing_files(mut self, skip: bool) -> Self {
+        self.skip_missing_files = skip;
+        self
+    }
+
+    /// Prefix every stored value with its write time in unix millis, read
+    /// back by `lookup_with_meta` and `modified_since`. Fixed once the store
+    /// holds data: opening it with a different setting fails.
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Encrypt values with AES-256-GCM under `key`. Keys are stored in the
+    /// clear; a value that fails authentication reads as corruption. Fixed
+    /// once the store holds data: opening it without the key, or with
+    /// another one, fails.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+}
+
+const WRITE_BUFFER_BYTES: usize = 64 * 1024;
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+const MAX_HINT_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+pub const MIN_CHUNK_BYTES: usize = 4 * 1024;
+const CHECKED_LEN: u64 = 64 * 1024;
+const VACUUM_CHUNKS: u64 = 16;
+const VACUUM_MIN_CHUNK: u64 = 1024 * 1024;
+const VACUUM_MAX_CHUNK: u64 = 64 * 1024 * 1024;
+
+struct Run {
+    id: FileId,
+    min: Vec<u8>,
+    max: Vec<u8>,
+    size: u64,
+}
+
+impl Run {
+    fn overlaps(&self, order: &Comparator, min: &[u8], max: &[u8]) -> bool {
+        order.compare(&self.min, max).is_le() && order.compare(min, &self.max).is_le()
+    }
+}
+
+/// What replaying one data file does to the index, worked out apart from
+/// the other files so that they can be scanned in parallel. Within a file
+/// later records shadow earlier ones, so only the last write of each key
+/// is kept, and applying the scans in file order gives what `replay` does.
+#[cfg(feature = "rayon")]
+struct FileScan {
+    /// Range tombstones, applied to the older files before `writes`.
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The last write of each key not covered by a later range tombstone:
+    /// where its value is, or `None` for a removal.
+    writes: BTreeMap<Vec<u8>, Option<RecordLocation>>,
+    /// Tombstones and the inserts shadowed within the file.
+    dead_bytes: u64,
+    /// Where a torn write at the end of the file starts.
+    torn: Option<u64>,
+}
+
+/// Sleeps whenever more bytes have been written than `rate` allows for the
+/// time elapsed since the start, and fails with `Interrupted` once `cancel`
+/// is set.
+struct Throttle<'a> {
+    rate: Option<u64>,
+    started: Instant,
+    bytes: u64,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> Throttle<'a> {
+    fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate: rate.filter(|rate| *rate > 0),
+            started: Instant::now(),
+            bytes: 0,
+            cancel: None,
+        }
+    }
+
+    fn cancel_on(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn wrote(&mut self, bytes: u64) -> io::Result<()> {
+        self.check()?;
+        if let Some(rate) = self.rate {
+            self.bytes += bytes;
+            let due = Duration::from_secs_f64(self.bytes as f64 / rate as f64);
+            if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(ahead);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompactionResult {
+    pub input_records: usize,
+    pub output_records: usize,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub reclaimed_bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// A `reduce` merged into a new file by `Store::prepare_reduce`, waiting for
+/// `Store::finish_reduce` to swap it in.
+pub struct PendingReduce {
+    dst: StoreFile,
+    index: Index,
+    inputs: Vec<FileId>,
+    active_len: u64,
+    input_records: usize,
+    input_bytes: u64,
+    started: Instant,
+}
+
+/// What `Store::doctor` found, with a one-line verdict for an operator.
+#[derive(Clone, Debug)]
+pub struct DoctorReport {
+    pub files: usize,
+    pub disk_bytes: u64,
+    pub keys: usize,
+    pub dead_bytes: u64,
+    /// Share of the bytes on disk a full compaction would reclaim.
+    pub dead_ratio: f64,
+    /// The first record that failed to decode, as (file, offset).
+    pub corrupt: Option<(FileId, u64)>,
+    /// A key found twice in a sorted run, as (file, key).
+    pub duplicate: Option<(FileId, Vec<u8>)>,
+    /// `"healthy"`, `"compaction advised"`, `"corruption detected at ..."` or
+    /// `"duplicate key ..."`.
+    pub recommendation: String,
+}
+
+/// Records and bytes of one data file, as found by `Store::file_stats`.
+/// Bytes count whole records; a record is live if the index points at it,
+/// so tombstones are always dead.
+#[derive(Clone, Debug)]
+pub struct FileStat {
+    pub file_id: FileId,
+    pub total_records: usize,
+    pub live_records: usize,
+    pub total_bytes: u64,
+    pub dead_bytes: u64,
+}
+
+/// What `Store::open_report` found in a store directory.
+#[derive(Clone, Debug, Default)]
+pub struct OpenReport {
+    /// Data files in the directory, including ones the manifest doesn't list.
+    pub files: usize,
+    pub bytes: u64,
+    /// The log `open` would append to; `None` if it would start a new one.
+    pub active: Option<FileId>,
+    /// Whether `open` would load the index checkpoint instead of replaying.
+    pub checkpoint: bool,
+}
+
+pub struct Store {
+    id: FileId,
+    next: u64,
+    base: PathBuf,
+    options: StoreOptions,
+    files: BTreeMap<FileId, StoreFile>,
+    index: BTreeMap<Vec<u8>, RecordLocation>,
+    /// The keys of `index` in the comparator's order, if that is not the
+    /// byte order the index is sorted in.
+    ordered: OrderedKeys,
+    levels: Vec<Vec<Run>>,
+    dead_bytes: u64,
+    /// When a compaction last dropped tombstones, in unix millis: removals
+    /// before then may no longer be on disk for `backup_incremental`.
+    compacted_at: u64,
+    cipher: Option<Cipher>,
+    /// Recorded in the manifest: see `Manifest::key_check`.
+    key_check: Vec<u8>,
+    /// Runs dropped by `skip_missing_files`, whose keys read as `Corrupt`
+    /// until `healed` by a write.
+    lost: Vec<Run>,
+    /// Keys in a `lost` run written or removed since the store was opened.
+    healed: BTreeSet<Vec<u8>>,
+}
+
+impl Store {
+    /// Opens the store in `base`, which must be an existing directory.
+    pub fn open(base: &str) -> kv::Result<Self> {
+        Self::open_with(base, StoreOptions::default())
+    }
+
+    /// Like `open`, but creates `base` first if it is missing.
+    pub fn open_or_create(base: &str) -> kv::Result<Self> {
+        Self::open_or_create_with(base, StoreOptions::default())
+    }
+
+    pub fn open_or_create_with(base: &str, options: StoreOptions) -> kv::Result<Self> {
+        std::fs::create_dir_all(base)?;
+        Self::open_with(base, options)
+    }
+
+    pub fn open_with(base: &str, options: StoreOptions) -> kv::Result<Self> {
+        // TODO: Compact files (in background)
+
+        let base = PathBuf::from(base);
+        if !options.is_valid() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        let names = &options.names;
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&base)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            if options.truncate
+                && (path == names.manifest(&base) || path == names.checkpoint(&base))
+            {
+                std::fs::remove_file(path)?;
+            } else if names.is_scratch(&name) {
+                warn!("removing {} left over from a compaction", path.display());
+                if entry.file_type()?.is_dir() {
+                    std::fs::remove_dir_all(path)?;
+                } else {
+                    std::fs::remove_file(path)?;
+                }
+            } else if let Some(id) = names.parse(&name) {
+                if options.truncate {
+                    std::fs::remove_file(entry.path())?;
+                } else {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort();
+
+        #[cfg(feature = "encryption")]
+        let cipher = options.encryption_key.as_ref().map(Cipher::new);
+        #[cfg(not(feature = "encryption"))]
+        let cipher = None;
+
+        let ordered = OrderedKeys::new(&options.comparator);
+        let mut this = Self {
+            id: FileId(0),
+            next: ids.last().map(|id| id.0 + 1).unwrap_or(1),
+            base,
+            options,
+            files: BTreeMap::default(),
+            index: BTreeMap::default(),
+            ordered,
+            levels: Vec::default(),
+            dead_bytes: 0,
+            compacted_at: 0,
+            cipher,
+            key_check: Vec::new(),
+            lost: Vec::new(),
+            healed: BTreeSet::new(),
+        };
+
+        let mut logs = ids;
+        let mut timestamps = false;
+        let mut comparator = order::BYTEWISE.to_string();
+        let mut key_check = None;
+        let mut lost = Vec::new();
+        if let Some(manifest) = manifest::read(&this.options.names.manifest(&this.base))? {
+            timestamps = manifest.timestamps;
+            comparator = manifest.comparator;
+            key_check = manifest.key_check;
+            this.compacted_at = manifest.compacted_at;
+            this.next = this.next.max(manifest.next);
+            for metas in manifest.levels {
+                let mut runs = Vec::with_capacity(metas.len());
+                for RunMeta { id, min, max } in metas {
+                    if !this.id_to_dat_path(&id).exists() {
+                        if !this.options.skip_missing_files {
+                            error!("file {} listed in the manifest is missing", id.0);
+                            return Err(kv::Error::Corrupt {
+                                file: id.0,
+                                offset: 0,
+                            });
+                        }
+                        warn!("dropping run {}: its file is missing", id.0);
+                        lost.push(Run {
+                            id,
+                            min,
+                            max,
+                            size: 0,
+                        });
+                        continue;
+                    }
+                    let file = this.id_to_file(&id)?;
+                    let size = file.offset;
+                    this.files.insert(id, file);
+                    runs.push(Run { id, min, max, size });
+                }
+                this.levels.push(runs);
+            }
+            for id in logs.iter() {
+                if *id != manifest.active && !this.files.contains_key(id) {
+                    warn!("ignoring file {} not listed in the manifest", id.0);
+                }
+            }
+            logs.retain(|id| *id == manifest.active);
+            if logs.is_empty() {
+                if !this.options.skip_missing_files {
+                    error!(
+                        "active log {} listed in the manifest is missing",
+                        manifest.active.0
+                    );
+                    return Err(kv::Error::Corrupt {
+                        file: manifest.active.0,
+                        offset: 0,
+                    });
+                }
+                warn!(
+                    "starting a new log: active log {} is missing",
+                    manifest.active.0
+                );
+            }
+            this.check_levels()?;
+        }
+
+        for id in logs.iter() {
+            this.files.insert(*id, this.id_to_file(id)?);
+        }
+        this.id = match logs.last() {
+            Some(id) => *id,
+            None => {
+                let id = FileId(this.next);
+                this.next += 1;
+                this.files.insert(id, this.id_to_file(&id)?);
+                id
+            }
+        };
+        this.size_buffer();
+
+        let blank = this.files.values().all(|file| file.is_blank());
+        let reordered = comparator != this.options.comparator.name();
+        let rekeyed = match (&key_check, &this.cipher) {
+            (None, _) => false,
+            (Some(check), None) => !check.is_empty(),
+            (Some(check), Some(cipher)) => !cipher.checks(check),
+        };
+        if !blank && (timestamps != this.options.timestamps || reordered || rekeyed) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        if let Some(cipher) = &this.cipher {
+            this.key_check = cipher.key_check();
+        }
+
+        let path = this.options.names.checkpoint(&this.base);
+        match checkpoint::read(&path, &this.fingerprint()?)? {
+            Some(checkpoint) => {
+                this.index = checkpoint.index;
+                this.ordered.reset(this.index.keys());
+                this.dead_bytes = checkpoint.dead_bytes;
+            }
+            None => {
+                let order: Vec<FileId> = this
+                    .levels
+                    .iter()
+                    .rev()
+                    .flat_map(|runs| runs.iter().map(|run| run.id))
+                    .chain(logs)
+                    .collect();
+                #[cfg(feature = "rayon")]
+                if order.len() > 1 {
+                    this.replay_parallel(&order)?;
+                } else {
+                    for id in order {
+                        this.replay(id)?;
+                    }
+                }
+                #[cfg(not(feature = "rayon"))]
+                for id in order {
+                    this.replay(id)?;
+                }
+            }
+        }
+
+        // Set once the replay is done, as until then nothing has been written.
+        this.lost = lost;
+        this.save_layout()?;
+        info!(
+            "opened {}: {} files, {} keys",
+            this.base.display(),
+            this.files.len(),
+            this.index.len()
+        );
+        Ok(this)
+    }
+
+    /// Inspects `base` the way `open` would, without replaying or changing
+    /// anything on disk.
+    pub fn open_report(base: &str) -> kv::Result<OpenReport> {
+        Self::open_report_with(base, &StoreOptions::default())
+    }
+
+    /// Like `open_report`, for a store whose files are named as in `options`.
+    pub fn open_report_with(base: &str, options: &StoreOptions) -> kv::Result<OpenReport> {
+        let base = PathBuf::from(base);
+        let names = &options.names;
+        let mut report = OpenReport::default();
+        let mut lens = BTreeMap::new();
+        for entry in std::fs::read_dir(&base)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = names.parse(&name) {
+                let len = entry.metadata()?.len();
+                report.files += 1;
+                report.bytes += len;
+                lens.insert(id, len);
+            }
+        }
+
+        let mut next = lens.keys().last().map(|id| id.0 + 1).unwrap_or(1);
+        let mut used: BTreeMap<FileId, u64> = BTreeMap::new();
+        match manifest::read(&names.manifest(&base))? {
+            Some(manifest) => {
+                next = next.max(manifest.next);
+                for meta in manifest.levels.iter().flatten() {
+                    used.insert(meta.id, lens.get(&meta.id).copied().unwrap_or(0));
+                }
+                report.active = lens
+                    .contains_key(&manifest.active)
+                    .then_some(manifest.active);
+            }
+            None => {
+                used = lens.clone();
+                report.active = lens.keys().last().copied();
+            }
+        }
+        match report.active {
+            Some(id) => used.insert(id, lens[&id]),
+            None => used.insert(FileId(next), 0),
+        };
+
+        let mut files = Vec::with_capacity(used.len());
+        for id in used.into_keys() {
+            match File::open(names.data(&base, &id)) {
+                Ok(file) => files.push(FileMark::of(id, &file)?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => files.push(FileMark::empty(id)),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        report.checkpoint = checkpoint::read(&names.checkpoint(&base), &files)?.is_some();
+        Ok(report)
+    }
+
+    /// Runs below L0 are ordered by key range alone, so two of them covering
+    /// the same keys leave no way to tell which one holds the latest value.
+    fn check_levels(&mut self) -> kv::Result<()> {
+        let order = &self.options.comparator;
+        for (level, runs) in self.levels.iter_mut().enumerate().skip(1) {
+            runs.sort_by(|a, b| order.compare(&a.min, &b.min));
+            for pair in runs.windows(2) {
+                if pair[0].overlaps(order, &pair[1].min, &pair[1].max) {
+                    let (a, b) = (pair[0].id.0, pair[1].id.0);
+                    error!("overlapping runs {} and {} in level {}", a, b, level);
+                    return Err(kv::Error::Unknown(format!(
+                        "overlapping runs {a} and {b} in level {level}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_from(
+        base: &str,
+        pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> kv::Result<Self> {
+        Self::create_from_with(base, StoreOptions::default(), pairs)
+    }
+
+    /// Builds a store from `pairs`. Fails if `base` already holds data,
+    /// unless `options` ask to truncate it.
+    pub fn create_from_with(
+        base: &str,
+        options: StoreOptions,
+        pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> kv::Result<Self> {
+        let mut store = Self::open_or_create_with(base, options)?;
+        if store.disk_record_count()? > 0 {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into());
+        }
+        store.extend(pairs)?;
+        Ok(store)
+    }
+
+    /// Loads the records of file `id` into the index. A bad record followed by
+    /// nothing that decodes is a torn write and gets truncated away; a bad
+    /// record followed by valid ones is reported as corruption.
+    fn replay(&mut self, id: FileId) -> kv::Result<()> {
+        let mut src = self.open_run(&id)?;
+        let len = src.file.metadata()?.len();
+        loop {
+            let offset = src.offset;
+            if offset >= len {
+                break;
+            }
+            let record = match src.read_record() {
+                Ok(record) => record,
+                Err(_) if src.valid_after(offset)? => {
+                    error!("corrupt record in file {} at offset {}", id.0, offset);
+                    return Err(kv::Error::Corrupt { file: id.0, offset });
+                }
+                Err(_) => {
+                    warn!(
+                        "truncating torn write in file {} at offset {}",
+                        id.0, offset
+                    );
+                    self.files.get_mut(&id).unwrap().truncate(offset)?;
+                    break;
+                }
+            };
+            match record {
+                Record::Insert(key, val) => {
+                    let entry = RecordLocation {
+                        file: id,
+                        offset: value_offset(src.encoding, offset, &key, &val),
+                        length: val.len() as u64,
+                    };
+                    self.apply_insert(key, entry);
+                }
+                Record::Remove(key) => {
+                    self.apply_remove(&key, src.offset - offset);
+                }
+                Record::RemoveRange(start, end) => {
+                    self.apply_remove_range(&start, &end, src.offset - offset);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `replay` of every file in `order`, with the files scanned on rayon's
+    /// thread pool and the scans applied one after another.
+    #[cfg(feature = "rayon")]
+    fn replay_parallel(&mut self, order: &[FileId]) -> kv::Result<()> {
+        use rayon::prelude::*;
+        let scans: Vec<FileScan> = order
+            .par_iter()
+            .map(|id| self.scan_file(*id))
+            .collect::<kv::Result<_>>()?;
+        for (id, scan) in order.iter().zip(scans) {
+            if let Some(offset) = scan.torn {
+                warn!(
+                    "truncating torn write in file {} at offset {}",
+                    id.0, offset
+                );
+                self.files.get_mut(id).unwrap().truncate(offset)?;
+            }
+            self.dead_bytes += scan.dead_bytes;
+            for (start, end) in scan.ranges {
+                self.apply_remove_range(&start, &end, 0);
+            }
+            for (key, write) in scan.writes {
+                match write {
+                    Some(entry) => self.apply_insert(key, entry),
+                    None => {
+                        self.apply_remove(&key, 0);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    fn scan_file(&self, id: FileId) -> kv::Result<FileScan> {
+        let mut src = self.open_run(&id)?;
+        let len = src.file.metadata()?.len();
+        let encoding = src.encoding;
+        let order = &self.options.comparator;
+        let mut scan = FileScan {
+            ranges: Vec::new(),
+            writes: BTreeMap::new(),
+            dead_bytes: 0,
+            torn: None,
+        };
+        let dead = |key: &[u8], write: Option<RecordLocation>| match write {
+            Some(old) => encoding.insert_len(key.len(), old.length as usize) as u64,
+            None => 0,
+        };
+        loop {
+            let offset = src.offset;
+            if offset >= len {
+                break;
+            }
+            let record = match src.read_record() {
+                Ok(record) => record,
+                Err(_) if src.valid_after(offset)? => {
+                    error!("corrupt record in file {} at offset {}", id.0, offset);
+                    return Err(kv::Error::Corrupt { file: id.0, offset });
+                }
+                Err(_) => {
+                    scan.torn = Some(offset);
+                    break;
+                }
+            };
+            match record {
+                Record::Insert(key, val) => {
+                    let entry = RecordLocation {
+                        file: id,
+                        offset: value_offset(encoding, offset, &key, &val),
+                        length: val.len() as u64,
+                    };
+                    if let Some(old) = scan.writes.insert(key.clone(), Some(entry)) {
+                        scan.dead_bytes += dead(&key, old);
+                    }
+                }
+                Record::Remove(key) => {
+                    scan.dead_bytes += src.offset - offset;
+                    if let Some(old) = scan.writes.insert(key.clone(), None) {
+                        scan.dead_bytes += dead(&key, old);
+                    }
+                }
+                Record::RemoveRange(start, end) => {
+                    scan.dead_bytes += src.offset - offset;
+                    scan.writes.retain(|key, write| {
+                        let covered = order.covers(&start, &end, key);
+                        if covered {
+                            scan.dead_bytes += dead(key, *write);
+                        }
+                        !covered
+                    });
+                    scan.ranges.push((start, end));
+                }
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Writes the index checkpoint, letting the next `open` skip replaying
+    /// the data files as long as none of them changes in the meantime.
+    pub fn checkpoint(&mut self) -> kv::Result<()> {
+        let files = self.fingerprint()?;
+        let path = self.options.names.checkpoint(&self.base);
+        checkpoint::write(&path, &files, &self.index, self.dead_bytes)?;
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> kv::Result<Vec<FileMark>> {
+        let mut files = Vec::with_capacity(self.files.len());
+        for (id, file) in self.files.iter() {
+            files.push(FileMark::of(*id, &file.file)?);
+        }
+        Ok(files)
+    }
+
+    fn save_layout(&self) -> kv::Result<()> {
+        telemetry::set(telemetry::FILES, self.files.len() as f64);
+        telemetry::set(telemetry::INDEX_KEYS, self.index.len() as f64);
+        let levels = self
+            .levels
+            .iter()
+            .map(|runs| {
+                runs.iter()
+                    .map(|run| RunMeta {
+                        id: run.id,
+                        min: run.min.clone(),
+                        max: run.max.clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let manifest = Manifest {
+            active: self.id,
+            next: self.next,
+            levels,
+            timestamps: self.options.timestamps,
+            comparator: self.options.comparator.name().to_string(),
+            compacted_at: self.compacted_at,
+            key_check: Some(self.key_check.clone()),
+        };
+        manifest::write(&self.options.names.manifest(&self.base), &manifest)?;
+        Ok(())
+    }
+
+    fn persist(&mut self) -> kv::Result<()> {
+        #[cfg(debug_assertions)]
+        self.check_len()?;
+        self.save_layout()?;
+        self.checkpoint()
+    }
+
+    /// Keys are arbitrary bytes, the empty key included: it is stored, ordered
+    /// first (by the default comparator) and compacted like any other key.
+    pub fn insert(&mut self, key: &[u8], val: &[u8]) -> kv::Result<()> {
+        self.write(key, val)?;
+        self.flush()
+    }
+
+    /// Writes a tombstone for `key` if it is present; an absent key has
+    /// nothing to shadow, so nothing gets written.
+    pub fn remove(&mut self, key: &[u8]) -> kv::Result<bool> {
+        if !self.index.contains_key(key) {
+            return Ok(false);
+        }
+        let file = self.file();
+        let offset = file.offset;
+        file.remove(key)?;
+        file.flush()?;
+        let tombstone = file.offset - offset;
+        let removed = self.apply_remove(key, tombstone);
+        telemetry::count(telemetry::REMOVES);
+        telemetry::set(telemetry::INDEX_KEYS, self.index.len() as f64);
+        Ok(removed)
+    }
+
+    /// Removes every key in `[start, end)` with a single range tombstone and
+    /// returns how many were present. Like `remove`, writes nothing if none is.
+    pub fn remove_range(&mut self, start: &[u8], end: &[u8]) -> kv::Result<usize> {
+        let empty = self.options.comparator.compare(start, end).is_ge();
+        if empty || self.keys_in(start, end).next().is_none() {
+            return Ok(0);
+        }
+        if self.file().start == 0 {
+            self.add_header()?;
+        }
+        let file = self.file();
+        let offset = file.offset;
+        file.remove_range(start, end)?;
+        file.flush()?;
+        let tombstone = file.offset - offset;
+        let removed = self.apply_remove_range(start, end, tombstone);
+        telemetry::count(telemetry::REMOVES);
+        telemetry::set(telemetry::INDEX_KEYS, self.index.len() as f64);
+        Ok(removed)
+    }
+
+    /// Moves a log from before files had a header, which cannot take range
+    /// tombstones, behind one in a new file that becomes the active log. The
+    /// old log is only deleted once the new one is synced and in the
+    /// manifest.
+    fn add_header(&mut self) -> kv::Result<()> {
+        let old = self.id;
+        self.flush()?;
+        let id = FileId(self.next);
+        self.next += 1;
+        let encoding = self.file().encoding;
+        let mut dst = StoreFile::make(id, self.id_to_dat_path(&id), encoding)?;
+        let copied = File::open(self.id_to_dat_path(&old)).and_then(|mut src| {
+            dst.file.seek(SeekFrom::Start(dst.start))?;
+            let copied = io::copy(&mut src, &mut dst.file)?;
+            dst.offset = dst.start + copied;
+            dst.sync()
+        });
+        if let Err(e) = copied {
+            std::fs::remove_file(self.id_to_dat_path(&id))?;
+            return Err(e.into());
+        }
+
+        for entry in self.index.values_mut() {
+            if entry.file == old {
+                entry.file = id;
+                entry.offset += dst.start;
+            }
+        }
+        self.files.insert(id, dst);
+        self.id = id;
+        self.size_buffer();
+        self.remove_files(&[old])
+    }
+
+    fn keys_in<'a>(&'a self, start: &'a [u8], end: &'a [u8]) -> impl Iterator<Item = &'a Vec<u8>> {
+        let range = (Bound::Included(start), Bound::Excluded(end));
+        self.entries(range.0, range.1).map(|(key, _)| key)
+    }
+
+    /// Index entries between `start` and `end` in key order. The index is
+    /// sorted by bytes, so under another comparator the keys come from
+    /// `ordered` and the entries are looked up one by one.
+    /// A reversed range is empty rather than a panic in `BTreeMap::range`.
+    fn entries(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Entries<'_> {
+        let order = &self.options.comparator;
+        if order.is_empty(start, end) {
+            return Entries::Empty;
+        }
+        if order.is_bytewise() {
+            return Entries::Index(self.index.range::<[u8], _>((start, end)));
+        }
+        Entries::Ordered(self.ordered.range(start, end), &self.index)
+    }
+
+    pub fn extend(
+        &mut self,
+        pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> kv::Result<()> {
+        for (key, val) in pairs {
+            self.write(&key, &val)?;
+        }
+        self.flush()
+    }
+
+    /// Sizes the write buffer of the active file for a bulk load of about
+    /// `expected_records` records holding `expected_bytes` of keys and values,
+    /// up to 16 MiB, so that `extend` writes it out in a few large writes.
+    /// The next compaction goes back to `StoreOptions::write_buffer_bytes`.
+    /// Nothing is preallocated: `open` replays a file to its end, and the
+    /// index has no capacity to reserve.
+    pub fn hint_load(&mut self, expected_records: usize, expected_bytes: u64) {
+        let floor = self.options.write_buffer_bytes;
+        let file = self.file();
+        let framing = expected_records.saturating_mul(file.encoding.max_header_len());
+        let bytes = (expected_bytes as usize).saturating_add(framing);
+        file.buffer_bytes = bytes.min(MAX_HINT_BUFFER_BYTES).max(floor);
+        file.buffer
+            .reserve(file.buffer_bytes.saturating_sub(file.buffer.len()));
+    }
+
+    pub fn flush(&mut self) -> kv::Result<()> {
+        self.file().flush()?;
+        Ok(())
+    }
+
+    /// Like `flush`, but only returns once the data files are on disk. Files
+    /// with nothing written since the last `sync` are skipped, so both are
+    /// cheap to call defensively.
+    pub fn sync(&mut self) -> kv::Result<()> {
+        for file in self.files.values_mut() {
+            file.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Like `sync`, for the files holding the live entries between `start`
+    /// and `end`, and the active file, which holds any removal in the range.
+    pub fn sync_range(&mut self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> kv::Result<()> {
+        let mut ids: BTreeSet<FileId> = self
+            .entries(start, end)
+            .map(|(_, entry)| entry.file)
+            .collect();
+        ids.insert(self.id);
+        for id in ids {
+            if let Some(file) = self.files.get_mut(&id) {
+                file.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, key: &[u8], val: &[u8]) -> kv::Result<()> {
+        if self.options.skip_identical_writes && self.is_stored(key, val)? {
+            return Ok(());
+        }
+        telemetry::record(telemetry::VALUE_BYTES, val.len() as f64);
+        let sealed = self.cipher.as_ref().map(|cipher| cipher.seal(key, val));
+        let val = sealed.as_deref().unwrap_or(val);
+        let stamped = self
+            .options
+            .timestamps
+            .then(|| [&now_millis().to_be_bytes()[..], val].concat());
+        let entry = self.file().insert(key, stamped.as_deref().unwrap_or(val))?;
+        self.apply_insert(key.to_vec(), entry);
+        telemetry::count(telemetry::INSERTS);
+        telemetry::set(telemetry::INDEX_KEYS, self.index.len() as f64);
+        Ok(())
+    }
+
+    fn is_stored(&self, key: &[u8], val: &[u8]) -> kv::Result<bool> {
+        let entry = match self.index.get(key) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let plain = self.cipher.is_none() && !self.options.timestamps;
+        if plain && entry.length != val.len() as u64 {
+            return Ok(false);
+        }
+        let mut buffer = Vec::new();
+        self.read_value_into(key, entry, &mut buffer)?;
+        Ok(buffer == val)
+    }
+
+    /// The length of the value at `entry` as `get` returns it, without the
+    /// timestamp and encryption overhead stored along with it.
+    pub(crate) fn value_len(&self, entry: &RecordLocation) -> u64 {
+        let mut overhead = 0;
+        if self.options.timestamps {
+            overhead += FIELD_LEN;
+        }
+        if let Some(cipher) = &self.cipher {
+            overhead += cipher.overhead();
+        }
+        entry.length.saturating_sub(overhead as u64)
+    }
+
+    /// Reads the value stored under `key` at `entry`, stripping its
+    /// timestamp (returned, if the store keeps them) and decrypting it if the
+    /// store is encrypted.
+    fn read_value_into(
+        &self,
+        key: &[u8],
+        entry: &RecordLocation,
+        buffer: &mut Vec<u8>,
+    ) -> kv::Result<Option<u64>> {
+        self.check_lost(key)?;
+        if self.options.verify_on_read {
+            self.verify_entry(key, entry)?;
+        }
+        buffer.resize(entry.length as usize, 0);
+        self.read_entry(entry, 0, &mut buffer[..])?;
+        let corrupt = || kv::Error::Corrupt {
+            file: entry.file.0,
+            offset: entry.offset,
+        };
+        let mut timestamp = None;
+        if self.options.timestamps {
+            let field = buffer.get(..FIELD_LEN).ok_or_else(corrupt)?;
+            timestamp = Some(u64::from_be_bytes(field.try_into().unwrap()));
+            buffer.drain(..FIELD_LEN);
+        }
+        match &self.cipher {
+            Some(cipher) if !cipher.open(key, buffer) => Err(corrupt()),
+            _ => Ok(timestamp),
+        }
+    }
+
+    /// Fills `buffer` from `at` bytes into the value `entry` points to. An
+    /// entry reaching outside of its file means the index and the data
+    /// disagree, and reads as corruption rather than as whatever is there.
+    fn read_entry(&self, entry: &RecordLocation, at: u64, buffer: &mut [u8]) -> kv::Result<()> {
+        let file = self.files.get(&entry.file).ok_or_else(|| {
+            error!("index entry in file {}, which is not open", entry.file.0);
+            kv::Error::Corrupt {
+                file: entry.file.0,
+                offset: entry.offset,
+            }
+        })?;
+        let corrupt = || {
+            warn!(
+                "index entry past the end of file {}: offset {} length {}",
+                entry.file.0, entry.offset, entry.length
+            );
+            kv::Error::Corrupt {
+                file: entry.file.0,
+                offset: entry.offset,
+            }
+        };
+        if !file.holds(entry.offset, entry.length)? {
+            return Err(corrupt());
+        }
+        match file.read(entry.offset + at, buffer) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(corrupt()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Checks that `entry` is the value of an insert of `key`.
+    fn verify_entry(&self, key: &[u8], entry: &RecordLocation) -> kv::Result<()> {
+        let corrupt = || kv::Error::Corrupt {
+            file: entry.file.0,
+            offset: entry.offset,
+        };
+        let encoding = self.files.get(&entry.file).ok_or_else(corrupt)?.encoding;
+        let head_len =
+            encoding.insert_len(key.len(), entry.length as usize) - entry.length as usize;
+        let start = entry
+            .offset
+            .checked_sub(head_len as u64)
+            .ok_or_else(corrupt)?;
+        let mut head = vec![0u8; head_len];
+        self.read_entry(
+            &RecordLocation::new(entry.file, start, head_len as u64),
+            0,
+            &mut head,
+        )?;
+
+        let mut at = 0;
+        let mut next = || {
+            let (val, len) = encoding.get(&head[at..])?;
+            at += len;
+            Some(val)
+        };
+        let (op, key_len, val_len) = (next(), next(), next());
+        let matches = op == Some(INSERT)
+            && key_len == Some(key.len() as u64)
+            && val_len == Some(entry.length)
+            && head[at..] == *key;
+        if !matches {
+            error!(
+                "index entry of key {} does not match file {} at offset {}",
+                util::hex(key),
+                entry.file.0,
+                start
+            );
+            return Err(corrupt());
+        }
+        Ok(())
+    }
+
+    fn apply_insert(&mut self, key: Vec<u8>, entry: RecordLocation) {
+        let key_len = key.len();
+        self.heal(&key);
+        if !self.options.comparator.is_bytewise() && !self.index.contains_key(&key) {
+            self.ordered.insert(&key);
+        }
+        if let Some(old) = self.index.insert(key, entry) {
+            self.dead_bytes += self.insert_len(key_len, &old);
+        }
+    }
+
+    /// Accounts for a tombstone of `tombstone` bytes on disk.
+    fn apply_remove(&mut self, key: &[u8], tombstone: u64) -> bool {
+        self.dead_bytes += tombstone;
+        self.heal(key);
+        match self.index.remove(key) {
+            Some(old) => {
+                self.ordered.remove(key);
+                self.dead_bytes += self.insert_len(key.len(), &old);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn apply_remove_range(&mut self, start: &[u8], end: &[u8], tombstone: u64) -> usize {
+        self.dead_bytes += tombstone;
+        if self.options.comparator.compare(start, end).is_ge() {
+            return 0;
+        }
+        let keys: Vec<Vec<u8>> = self.keys_in(start, end).cloned().collect();
+        for key in keys.iter() {
+            self.apply_remove(key, 0);
+        }
+        keys.len()
+    }
+
+    fn heal(&mut self, key: &[u8]) {
+        if self.lost_run(key).is_some() {
+            self.healed.insert(key.to_vec());
+        }
+    }
+
+    /// The dropped run that may have held a newer version of `key`, unless
+    /// `key` has been written since.
+    fn lost_run(&self, key: &[u8]) -> Option<&Run> {
+        let order = &self.options.comparator;
+        let run = self.lost.iter().find(|run| run.overlaps(order, key, key))?;
+        (!self.healed.contains(key)).then_some(run)
+    }
+
+    /// Fails with `Corrupt` for a key whose latest version may have been in
+    /// a run dropped by `skip_missing_files`.
+    fn check_lost(&self, key: &[u8]) -> kv::Result<()> {
+        match self.lost_run(key) {
+            Some(run) => Err(kv::Error::Corrupt {
+                file: run.id.0,
+                offset: 0,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// On-disk size of the insert record that `entry` points into.
+    fn insert_len(&self, key_len: usize, entry: &RecordLocation) -> u64 {
+        let encoding = self
+            .files
+            .get(&entry.file)
+            .map(|file| file.encoding)
+            .unwrap_or_default();
+        encoding.insert_len(key_len, entry.length as usize) as u64
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&[u8], &[u8]) -> bool) -> kv::Result<usize> {
+        let mut doomed = Vec::new();
+        for entry in self.iter() {
+            let (key, val) = entry?;
+            if !f(&key, &val) {
+                doomed.push(key);
+            }
+        }
+        for key in doomed.iter() {
+            self.remove(key)?;
+        }
+        Ok(doomed.len())
+    }
+
+    /// Bytes on disk taken by superseded inserts and tombstones, i.e. what a
+    /// full compaction would reclaim.
+    pub fn dead_bytes(&self) -> u64 {
+        self.dead_bytes
+    }
+
+    pub fn get(&self, key: &[u8]) -> kv::Result<Option<Vec<u8>>> {
+        let timer = Timer::start();
+        telemetry::count(telemetry::LOOKUPS);
+        let val = match self.index.get(key) {
+            Some(entry) => {
+                let mut buffer = Vec::new();
+                self.read_value_into(key, entry, &mut buffer)?;
+                Some(buffer)
+            }
+            None => {
+                self.check_lost(key)?;
+                None
+            }
+        };
+        timer.stop(telemetry::LOOKUP_SECONDS);
+        Ok(val)
+    }
+
+    #[deprecated(note = "use `get`")]
+    pub fn lookup(&self, key: &[u8]) -> kv::Result<Option<Vec<u8>>> {
+        self.get(key)
+    }
+
+    /// Values of `keys`, in the same order.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> kv::Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Like `multi_get`, with the value reads spread over rayon's thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn par_multi_get(&self, keys: &[&[u8]]) -> kv::Result<Vec<Option<Vec<u8>>>> {
+        use rayon::prelude::*;
+        keys.par_iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Copies the value of `key` to `out` in chunks rather than reading it
+    /// whole, and returns whether the key exists. Encrypted values can only
+    /// be authenticated whole, so those are still read into memory first.
+    pub fn read_value<W: Write>(&self, key: &[u8], mut out: W) -> kv::Result<bool> {
+        self.check_lost(key)?;
+        let entry = match self.index.get(key) {
+            Some(entry) => *entry,
+            None => return Ok(false),
+        };
+        if self.cipher.is_some() {
+            let mut buffer = Vec::new();
+            self.read_value_into(key, &entry, &mut buffer)?;
+            out.write_all(&buffer)?;
+            return Ok(true);
+        }
+        if self.options.verify_on_read {
+            self.verify_entry(key, &entry)?;
+        }
+        let skip = if self.options.timestamps {
+            FIELD_LEN as u64
+        } else {
+            0
+        };
+        if entry.length < skip {
+            return Err(kv::Error::Corrupt {
+                file: entry.file.0,
+                offset: entry.offset,
+            });
+        }
+        let mut chunk = vec![0u8; READ_CHUNK_BYTES.min((entry.length - skip) as usize)];
+        let mut at = skip;
+        while at < entry.length {
+            let len = chunk.len().min((entry.length - at) as usize);
+            self.read_entry(&entry, at, &mut chunk[..len])?;
+            out.write_all(&chunk[..len])?;
+            at += len as u64;
+        }
+        Ok(true)
+    }
+
+    /// The value of `key` along with the time it was written, in unix millis.
+    /// Requires a store opened with `StoreOptions::timestamps`.
+    pub fn lookup_with_meta(&self, key: &[u8]) -> kv::Result<Option<(Vec<u8>, u64)>> {
+        if !self.options.timestamps {
+            return Err(io::Error::from(io::ErrorKind::Unsupported).into());
+        }
+        Ok(self
+            .lookup_stamped(key)?
+            .map(|(val, timestamp)| (val, timestamp.unwrap_or_default())))
+    }
+
+    pub fn get_with_metadata(&self, key: &[u8]) -> kv::Result<Option<(Vec<u8>, RecordLocation)>> {
+        let val = match self.get(key)? {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        Ok(Some((val, self.index[key])))
+    }
+
+    fn lookup_stamped(&self, key: &[u8]) -> kv::Result<Option<(Vec<u8>, Option<u64>)>> {
+        self.check_lost(key)?;
+        let entry = match self.index.get(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut buffer = Vec::new();
+        let timestamp = self.read_value_into(key, entry, &mut buffer)?;
+        Ok(Some((buffer, timestamp)))
+    }
+
+    /// Live keys, in order, last written at or after `since` (unix millis).
+    /// Requires a store opened with `StoreOptions::timestamps`.
+    pub fn modified_since(&self, since: u64) -> kv::Result<Vec<Vec<u8>>> {
+        if !self.options.timestamps {
+            return Err(io::Error::from(io::ErrorKind::Unsupported).into());
+        }
+        let mut keys = Vec::new();
+        let mut field = [0u8; FIELD_LEN];
+        for (key, entry) in self.entries(Bound::Unbounded, Bound::Unbounded) {
+            if entry.length < FIELD_LEN as u64 {
+                return Err(kv::Error::Corrupt {
+                    file: entry.file.0,
+                    offset: entry.offset,
+                });
+            }
+            self.read_entry(entry, 0, &mut field)?;
+            if u64::from_be_bytes(field) >= since {
+                keys.push(key.clone());
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Writes what changed since the mark `since` to a data file at `dst` and
+    /// returns the mark for the next call; `since = 0` gives a full backup.
+    /// Values written at or after the mark are included, so a write in the
+    /// same millisecond as the previous backup is not missed (applying the
+    /// overlap twice is harmless). Removals travel as the tombstones still on
+    /// disk, so this fails once a compaction has dropped tombstones at or
+    /// after the mark: take a full backup then.
+    /// A range tombstone also carries every live key it covers, whatever its
+    /// age, as replaying the range clears them on the other side.
+    /// Values are written decrypted. Requires `StoreOptions::timestamps`.
+    pub fn backup_incremental(&self, since: u64, dst: &str) -> kv::Result<u64> {
+        if !self.options.timestamps {
+            return Err(io::Error::from(io::ErrorKind::Unsupported).into());
+        }
+        if since > 0 && self.compacted_at >= since {
+            return Err(kv::Error::Unknown(
+                "tombstones compacted away since the mark".to_string(),
+            ));
+        }
+        let mut out = StoreFile::make(FileId(0), dst, self.options.encoding())?;
+
+        let mut removed = BTreeSet::new();
+        let mut ranges = Vec::new();
+        for id in self.files.keys() {
+            for record in self.open_run(id)? {
+                match record {
+                    Record::Remove(key) if !self.index.contains_key(&key) => {
+                        removed.insert(key);
+                    }
+                    Record::RemoveRange(start, end) => ranges.push((start, end)),
+                    _ => (),
+                }
+            }
+        }
+        for key in removed {
+            out.remove(&key)?;
+        }
+        for (start, end) in ranges.iter() {
+            out.remove_range(start, end)?;
+        }
+
+        let mut mark = since;
+        let mut val = Vec::new();
+        for (key, entry) in self.index.iter() {
+            let timestamp = self
+                .read_value_into(key, entry, &mut val)?
+                .unwrap_or_default();
+            let covered = ranges
+                .iter()
+                .any(|(start, end)| self.options.comparator.covers(start, end, key));
+            if timestamp >= since || covered {
+                out.insert(key, &val)?;
+                mark = mark.max(timestamp);
+            }
+        }
+        out.sync()?;
+        Ok(mark)
+    }
+
+    /// Applies the records of a file written by `backup_incremental`,
+    /// returning how many were applied.
+    pub fn apply_incremental(&mut self, src: &str) -> kv::Result<usize> {
+        let file = File::open(src)?;
+        self.apply_log(io::BufReader::new(file))
+    }
+
+    /// Applies a stream of records in the data file format, with or without
+    /// the file header, and returns how many were applied. Stops at the first
+    /// record that does not decode, keeping the ones before it.
+    pub fn apply_log<R: Read>(&mut self, mut src: R) -> kv::Result<usize> {
+        let bad = |offset: u64| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad record at offset {offset}"),
+            )
+        };
+        let mut field = [0u8; FIELD_LEN];
+        let mut offset = 0;
+        if !read_field(&mut src, &mut field).map_err(|_| bad(0))? {
+            return Ok(0);
+        }
+        let mut encoding = Encoding::default();
+        let mut ranges = false;
+        let mut head = Some(ByteOrder::Big.decode(field));
+        if let Some(found) = format::parse_file_header(&field)? {
+            (encoding, ranges) = found;
+            offset = FILE_HEADER_LEN as u64;
+            head = None;
+        }
+
+        let mut count = 0;
+        loop {
+            let op = match head.take() {
+                Some(op) => op,
+                None => match read_int(&mut src, encoding).map_err(|_| bad(offset))? {
+                    Some(op) => op,
+                    None => break,
+                },
+            };
+            let record =
+                read_log_record(&mut src, encoding, ranges, op).map_err(|_| bad(offset))?;
+            offset += record.encoded_len(encoding) as u64;
+            match record {
+                Record::Insert(key, val) => self.write(&key, &val)?,
+                Record::Remove(key) => {
+                    self.remove(&key)?;
+                }
+                Record::RemoveRange(start, end) => {
+                    self.remove_range(&start, &end)?;
+                }
+            }
+            count += 1;
+        }
+        self.flush()?;
+        Ok(count)
+    }
+
+    /// Live entries in key order. Iteration goes through the index, so it is
+    /// sorted and reflects the latest writes whether or not the store has
+    /// been compacted.
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Entries with keys between `start` and `end`, in order. Reversed
+    /// bounds give no entries.
+    pub fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Iter<'_> {
+        Iter {
+            entries: self.entries(start, end),
+            store: self,
+        }
+    }
+
+    /// Entries with keys from `start` on, in order.
+    pub fn iter_from(&self, start: &[u8]) -> Iter<'_> {
+        self.range(Bound::Included(start), Bound::Unbounded)
+    }
+
+    /// Up to `limit` entries after the `after` key (from the start if `None`),
+    /// plus the cursor to pass for the next page, `None` once exhausted.
+    pub fn scan_page(
+        &self,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> kv::Result<(Vec<Entry>, Option<Vec<u8>>)> {
+        let start = after.map(Bound::Excluded).unwrap_or(Bound::Unbounded);
+        let mut iter = self.range(start, Bound::Unbounded);
+        let page = iter.by_ref().take(limit).collect::<kv::Result<Vec<_>>>()?;
+        let cursor = match page.last() {
+            Some((key, _)) if iter.entries.next().is_some() => Some(key.clone()),
+            _ => None,
+        };
+        Ok((page, cursor))
+    }
+
+    /// Calls `f` with every live entry in key order, reading each value into
+    /// one buffer that is reused across the scan. Stops at the first error.
+    pub fn scan(&self, buffer: &mut Vec<u8>, mut f: impl FnMut(&[u8], &[u8])) -> kv::Result<()> {
+        for (key, entry) in self.entries(Bound::Unbounded, Bound::Unbounded) {
+            self.read_value_into(key, entry, buffer)?;
+            f(key, buffer);
+        }
+        Ok(())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| key.as_slice())
+    }
+
+    /// How many live keys start with `prefix`, counted in the index alone.
+    /// Prefixes are a matter of bytes, whatever the comparator.
+    pub fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let end = prefix_end(prefix);
+        let end = end
+            .as_deref()
+            .map(Bound::Excluded)
+            .unwrap_or(Bound::Unbounded);
+        self.index
+            .range::<[u8], _>((Bound::Included(prefix), end))
+            .count()
+    }
+
+    pub fn min_key(&self) -> Option<&[u8]> {
+        let mut entries = self.entries(Bound::Unbounded, Bound::Unbounded);
+        entries.next().map(|(key, _)| key.as_slice())
+    }
+
+    pub fn max_key(&self) -> Option<&[u8]> {
+        let mut entries = self.entries(Bound::Unbounded, Bound::Unbounded);
+        entries.next_back().map(|(key, _)| key.as_slice())
+    }
+
+    fn id_to_dir_path(&self, id: &FileId) -> PathBuf {
+        self.id_to_path(id, "")
+    }
+
+    fn id_to_dat_path(&self, id: &FileId) -> PathBuf {
+        self.options.names.data(&self.base, id)
+    }
+
+    fn id_to_path(&self, id: &FileId, extension: &str) -> PathBuf {
+        self.options.names.path(&self.base, id, extension)
+    }
+
+    fn id_to_file(&self, id: &FileId) -> kv::Result<StoreFile> {
+        let file = StoreFile::open(*id, self.id_to_dat_path(id), self.options.encoding())?;
+        Ok(file)
+    }
+
+    /// The id the next new data file will get; ids only ever increase.
+    pub fn next_file_id(&self) -> FileId {
+        FileId(self.next)
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base
+    }
+
+    pub fn active_file_path(&self) -> PathBuf {
+        self.id_to_dat_path(&self.id)
+    }
+
+    /// Logical size of the active file, header and unflushed writes included.
+    pub fn active_file_bytes(&self) -> u64 {
+        self.files[&self.id].offset
+    }
+
+    /// Live keys. Every write keeps the index up to date, so this is exact
+    /// and costs nothing, compacted or not.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Replays the data files into a plain set of keys and asserts that it
+    /// holds as many as the index. Slow; only for debug builds, where every
+    /// compaction runs it.
+    #[cfg(debug_assertions)]
+    fn check_len(&self) -> kv::Result<()> {
+        let order = &self.options.comparator;
+        let mut keys = BTreeSet::new();
+        for id in self.replay_order() {
+            for record in self.open_run(&id)? {
+                match record {
+                    Record::Insert(key, _) => {
+                        keys.insert(key);
+                    }
+                    Record::Remove(key) => {
+                        keys.remove(&key);
+                    }
+                    Record::RemoveRange(start, end) => {
+                        keys.retain(|key: &Vec<u8>| !order.covers(&start, &end, key));
+                    }
+                }
+            }
+        }
+        debug_assert_eq!(
+            self.index.len(),
+            keys.len(),
+            "index out of sync with the data files"
+        );
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The records of the active file in key order, which only holds right
+    /// after a `reduce`: once something is written the file is a log again,
+    /// and the iterator ends with an error at the first key out of order.
+    /// It borrows the store, so no compaction can replace the file under it.
+    pub fn sorted_records(&mut self) -> impl Iterator<Item = kv::Result<Record>> + '_ {
+        let mut src = self
+            .flush()
+            .and_then(|()| self.open_run(&self.id))
+            .map_err(Some);
+        let order = self.options.comparator.clone();
+        let mut prev: Option<Vec<u8>> = None;
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            let src = match &mut src {
+                Ok(src) => src,
+                Err(e) => return e.take().map(Err),
+            };
+            if failed {
+                return None;
+            }
+            let record = src.next()?;
+            let later = |prev: &[u8]| order.compare(prev, record.key()).is_gt();
+            if prev.as_deref().map(later).unwrap_or(false) {
+                failed = true;
+                let id = src.id.0;
+                return Some(Err(kv::Error::Unknown(format!("file {id} is not sorted"))));
+            }
+            prev = Some(record.key().to_vec());
+            Some(Ok(record))
+        })
+    }
+
+    /// Every record physically stored, superseded ones and tombstones
+    /// included, with the file and offset it is at. Files come oldest first,
+    /// in the order `open` replays them. Like `sorted_records`, it borrows
+    /// the store for as long as it reads the files.
+    pub fn iter_raw(&mut self) -> impl Iterator<Item = kv::Result<(FileId, u64, Record)>> + '_ {
+        let srcs: Vec<kv::Result<StoreFile>> = match self.flush() {
+            Ok(()) => self
+                .replay_order()
+                .iter()
+                .map(|id| self.open_run(id))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        srcs.into_iter().flat_map(|src| {
+            let (mut src, err) = match src {
+                Ok(src) => (Some(src), None),
+                Err(e) => (None, Some(Err(e))),
+            };
+            err.into_iter().chain(std::iter::from_fn(move || {
+                let src = src.as_mut()?;
+                let (offset, record) = src.next_with_offset()?;
+                Some(Ok((src.id, offset, record)))
+            }))
+        })
+    }
+
+    /// Checks that every data file decodes to its end and sums up how much of
+    /// the store is dead weight.
+    pub fn doctor(&mut self) -> kv::Result<DoctorReport> {
+        self.flush()?;
+        let runs: BTreeSet<FileId> = self.levels.iter().flatten().map(|run| run.id).collect();
+        let mut corrupt = None;
+        let mut duplicate = None;
+        for id in self.files.keys() {
+            let mut src = self.open_run(id)?;
+            let len = src.file.metadata()?.len();
+            let mut prev: Option<Vec<u8>> = None;
+            while src.offset < len {
+                let record = match src.read_record() {
+                    Ok(record) => record,
+                    Err(_) => {
+                        corrupt = Some((*id, src.offset));
+                        break;
+                    }
+                };
+                // A range tombstone may share its start key with the insert
+                // after it; any other repeat in a run is a merge bug.
+                if !runs.contains(id) || record.kind() == RecordKind::RemoveRange {
+                    continue;
+                }
+                if duplicate.is_none() && prev.as_deref() == Some(record.key()) {
+                    duplicate = Some((*id, record.key().to_vec()));
+                }
+                prev = Some(record.key().to_vec());
+            }
+            if corrupt.is_some() {
+                break;
+            }
+        }
+
+        let disk_bytes = self.disk_bytes()?;
+        let dead_ratio = self.dead_ratio()?;
+        let recommendation = match (corrupt, &duplicate) {
+            (Some((file, offset)), _) => {
+                format!("corruption detected at file {} offset {}", file.0, offset)
+            }
+            (None, Some((file, key))) => {
+                format!("duplicate key {} in sorted file {}", util::hex(key), file.0)
+            }
+            _ if dead_ratio > self.options.compact_dead_ratio => "compaction advised".to_string(),
+            _ => "healthy".to_string(),
+        };
+        Ok(DoctorReport {
+            files: self.files.len(),
+            disk_bytes,
+            keys: self.index.len(),
+            dead_bytes: self.dead_bytes,
+            dead_ratio,
+            corrupt,
+            duplicate,
+            recommendation,
+        })
+    }
+
+    /// Scans every data file for how much of it is still live, to tell which
+    /// ones a compaction would gain the most from.
+    pub fn file_stats(&mut self) -> kv::Result<Vec<FileStat>> {
+        self.flush()?;
+        let mut stats = Vec::with_capacity(self.files.len());
+        for id in self.files.keys() {
+            let mut src = self.open_run(id)?;
+            let mut stat = FileStat {
+                file_id: *id,
+                total_records: 0,
+                live_records: 0,
+                total_bytes: 0,
+                dead_bytes: 0,
+            };
+            while let Some((offset, record)) = src.next_with_offset() {
+                let len = src.offset - offset;
+                stat.total_records += 1;
+                stat.total_bytes += len;
+                if self.is_live(id, offset, &record) {
+                    stat.live_records += 1;
+                } else {
+                    stat.dead_bytes += len;
+                }
+            }
+            stats.push(stat);
+        }
+        Ok(stats)
+    }
+
+    pub fn disk_record_count(&mut self) -> kv::Result<usize> {
+        let mut count = 0;
+        for id in self.files.keys() {
+            count += self.open_run(id)?.count();
+        }
+        Ok(count)
+    }
+
+    /// Merges everything into a single new file. The old files stay in place
+    /// until the merged one is synced and recorded in the manifest, so a crash
+    /// at any point leaves a store that reopens with all of its data.
+    ///
+    /// `limit` is the size of the sorted chunks the active file is cut into,
+    /// and so about the most memory the sort holds at once; the merge then
+    /// keeps one record per chunk. A zero `limit` is rejected and smaller
+    /// ones are raised to `MIN_CHUNK_BYTES`, as a record larger than `limit`
+    /// gets a chunk of its own.
+    pub fn reduce(&mut self, limit: usize) -> kv::Result<CompactionResult> {
+        let pending = self.prepare_reduce(limit)?;
+        self.finish_reduce(pending)
+    }
+
+    /// The merge half of `reduce`, which only reads the store: behind a lock,
+    /// this runs under a read lock and readers carry on against the old files
+    /// meanwhile. At most one may be pending at a time.
+    pub fn prepare_reduce(&self, limit: usize) -> kv::Result<PendingReduce> {
+        let throttle = Throttle::new(self.options.compaction_rate_limit);
+        self.prepare_reduce_with(limit, throttle)
+    }
+
+    /// `reduce` that stops with an `Interrupted` error soon after `cancel` is
+    /// set, leaving the store as it was: the merged file only replaces the
+    /// old ones once it is complete. Always a full merge with a `run_bytes`
+    /// limit, as no other strategy keeps its output apart until the end.
+    pub fn compact_cancelable(&mut self, cancel: &AtomicBool) -> kv::Result<CompactionResult> {
+        let throttle = Throttle::new(self.options.compaction_rate_limit).cancel_on(cancel);
+        let pending = self.prepare_reduce_with(self.options.run_bytes as usize, throttle)?;
+        self.finish_reduce(pending)
+    }
+
+    fn prepare_reduce_with(
+        &self,
+        limit: usize,
+        mut throttle: Throttle,
+    ) -> kv::Result<PendingReduce> {
+        if limit == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        let limit = limit.max(MIN_CHUNK_BYTES);
+        let started = Instant::now();
+        let input_bytes = self.disk_bytes()?;
+        info!(
+            "reduce started: {} files, {} bytes",
+            self.files.len(),
+            input_bytes
+        );
+        let order = &self.options.comparator;
+        let mut active = self.open_run(&self.id)?;
+        let len = active.file.metadata()?.len() - active.start;
+
+        // Named apart until the swap, so that the id can still go to another
+        // file should this one be dropped.
+        let id = FileId(self.next);
+        let path = self.id_to_path(&id, ".reduce");
+        let mut dst = StoreFile::make(id, &path, self.options.encoding())?;
+        let dir = self.id_to_dir_path(&self.id);
+
+        // With nothing else to merge, an active file that fits in one chunk
+        // is sorted in memory and written out directly.
+        let single = self.levels.iter().all(|runs| runs.is_empty()) && len <= limit as u64;
+        let merged = if single {
+            let records = sort_chunk(active.by_ref().collect(), order);
+            merge_chunk(&mut dst, records, &mut throttle).map_err(kv::Error::from)
+        } else {
+            let names = &self.options.names;
+            let merged = self.open_runs().and_then(|mut srcs| {
+                let chunks = split(&mut active, &dir, names, limit, order, &throttle);
+                srcs.append(&mut chunks?);
+                Ok(merge(&mut dst, &mut srcs, order, &mut throttle)?)
+            });
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            merged
+        };
+        let (index, input_records) = match merged {
+            Ok(merged) => merged,
+            Err(e) => {
+                std::fs::remove_file(&path)?;
+                return Err(e);
+            }
+        };
+        dst.sync()?;
+
+        Ok(PendingReduce {
+            dst,
+            index,
+            inputs: self.reduce_inputs(),
+            active_len: active.offset,
+            input_records,
+            input_bytes,
+            started,
+        })
+    }
+
+    /// Swaps in the file merged by `prepare_reduce`, carrying over whatever
+    /// was written to the active file since. Fails, dropping the merged file,
+    /// if any compaction changed the set of files in the meantime.
+    pub fn finish_reduce(&mut self, pending: PendingReduce) -> kv::Result<CompactionResult> {
+        let id = pending.dst.id;
+        let path = self.id_to_path(&id, ".reduce");
+        if pending.inputs != self.reduce_inputs() || FileId(self.next) != id {
+            std::fs::remove_file(path)?;
+            return Err(kv::Error::Unknown(
+                "store changed while reducing".to_string(),
+            ));
+        }
+        std::fs::rename(path, self.id_to_dat_path(&id))?;
+        self.next += 1;
+        let old = self.id;
+        self.flush()?;
+        let mut tail = self.open_run(&old)?;
+        tail.offset = pending.active_len;
+
+        for id in pending.inputs.iter() {
+            self.files.remove(id);
+        }
+        self.levels.clear();
+        self.files.insert(id, pending.dst);
+        self.id = id;
+        self.size_buffer();
+        self.index = pending.index;
+        self.ordered.reset(self.index.keys());
+        self.dead_bytes = 0;
+        self.compacted_at = now_millis();
+
+        let mut input_records = pending.input_records;
+        while let Ok(record) = tail.read_record() {
+            let file = self.file();
+            let at = file.offset;
+            file.exec(&record)?;
+            let len = file.offset - at;
+            match record {
+                Record::Insert(key, val) => {
+                    let entry = RecordLocation {
+                        file: id,
+                        offset: value_offset(file.encoding, at, &key, &val),
+                        length: val.len() as u64,
+                    };
+                    self.apply_insert(key, entry);
+                }
+                Record::Remove(key) => {
+                    self.apply_remove(&key, len);
+                }
+                Record::RemoveRange(start, end) => {
+                    self.apply_remove_range(&start, &end, len);
+                }
+            }
+            input_records += 1;
+        }
+        self.file().sync()?;
+        let output_bytes = self.file().offset;
+        self.persist()?;
+
+        for id in pending.inputs.iter() {
+            std::fs::remove_file(self.id_to_dat_path(id))?;
+        }
+        let result = CompactionResult {
+            input_records,
+            output_records: self.index.len(),
+            input_bytes: pending.input_bytes,
+            output_bytes,
+            reclaimed_bytes: pending.input_bytes.saturating_sub(output_bytes),
+            elapsed: pending.started.elapsed(),
+        };
+        info!(
+            "reduce finished: active file {}, reclaimed {} bytes in {:?}",
+            id.0, result.reclaimed_bytes, result.elapsed
+        );
+        Ok(result)
+    }
+
+    /// Runs from the deepest level up, then the active file: later files
+    /// shadow earlier ones.
+    fn replay_order(&self) -> Vec<FileId> {
+        let runs = self
+            .levels
+            .iter()
+            .rev()
+            .flat_map(|runs| runs.iter().map(|run| run.id));
+        runs.chain([self.id]).collect()
+    }
+
+    /// The files a `reduce` merges: every run, then the active file.
+    fn reduce_inputs(&self) -> Vec<FileId> {
+        let runs = self.levels.iter().flatten().map(|run| run.id);
+        runs.chain([self.id]).collect()
+    }
+
+    pub fn compact_into(&mut self, dst: &str) -> kv::Result<Store> {
+        let mut store = Store::open_or_create_with(dst, self.options.clone())?;
+        if store.disk_record_count()? > 0 {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into());
+        }
+
+        let mut srcs = self.open_runs()?;
+        let dir = self.id_to_dir_path(&self.id);
+        let (names, order) = (&self.options.names, &self.options.comparator);
+        let mut throttle = Throttle::new(self.options.compaction_rate_limit);
+        let file = self.files.get_mut(&self.id).unwrap();
+        let limit = self.options.run_bytes as usize;
+        let mut chunks = split(file, &dir, names, limit, order, &throttle)?;
+        srcs.append(&mut chunks);
+        let order = &self.options.comparator;
+        let (index, _) = merge(store.file(), &mut srcs, order, &mut throttle)?;
+        store.index = index;
+        store.ordered.reset(store.index.keys());
+        store.persist()?;
+
+        let path = self.id_to_dir_path(&self.id);
+        std::fs::remove_dir_all(&path)?;
+        Ok(store)
+    }
+
+    /// Merges everything into about `target_files` sorted runs of similar
+    /// size, cut from the live bytes, and returns how many were written.
+    /// Runs split only between keys, so there can be fewer of them, and
+    /// they stay at the first level whatever the compaction strategy.
+    pub fn reduce_into_files(&mut self, target_files: usize) -> kv::Result<usize> {
+        if target_files == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        if !self.file().is_blank() {
+            self.flush_active()?;
+        }
+        let runs = self.levels.iter().flatten();
+        let ids: Vec<FileId> = runs.map(|run| run.id).collect();
+        let mut srcs = self.open_runs()?;
+        let input = data_bytes(&srcs)?;
+        let live = input.saturating_sub(self.dead_bytes);
+        let limit = live.div_ceil(target_files as u64).max(1);
+        let runs = self.write_runs(&mut srcs, &ids, input, limit, true)?;
+
+        let count = runs.len();
+        self.levels = vec![runs];
+        self.remove_files(&ids)?;
+        Ok(count)
+    }
+
+    pub fn vacuum(&mut self) -> kv::Result<CompactionResult> {
+        let limit = (self.disk_bytes()? / VACUUM_CHUNKS).clamp(VACUUM_MIN_CHUNK, VACUUM_MAX_CHUNK);
+        self.reduce(limit as usize)
+    }
+
+    /// Runs `compact` if the dead bytes or the number of files are past the
+    /// thresholds in the options, and returns whether it did.
+    pub fn compact_if_needed(&mut self) -> kv::Result<bool> {
+        if self.dead_ratio()? <= self.options.compact_dead_ratio
+            && self.files.len() <= self.options.compact_max_files
+        {
+            return Ok(false);
+        }
+        self.compact()?;
+        Ok(true)
+    }
+
+    fn dead_ratio(&self) -> kv::Result<f64> {
+        Ok(self.dead_bytes as f64 / self.disk_bytes()?.max(1) as f64)
+    }
+
+    fn disk_bytes(&self) -> kv::Result<u64> {
+        let mut total = 0;
+        for file in self.files.values() {
+            total += file.file.metadata()?.len();
+        }
+        Ok(total)
+    }
+
+    pub fn compact(&mut self) -> kv::Result<()> {
+        let strategy = self.options.compaction;
+        if strategy == CompactionStrategy::Full {
+            return self.reduce(self.options.run_bytes as usize).map(|_| ());
+        }
+        let input_bytes = self.disk_bytes()?;
+        info!("{:?} compaction started: {} bytes", strategy, input_bytes);
+        if strategy == CompactionStrategy::Leveled {
+            self.compact_leveled()?;
+        } else {
+            self.compact_tiered()?;
+        }
+        self.persist()?;
+        let reclaimed = input_bytes.saturating_sub(self.disk_bytes()?);
+        info!(
+            "{:?} compaction finished: reclaimed {} bytes",
+            strategy, reclaimed
+        );
+        Ok(())
+    }
+
+    fn compact_leveled(&mut self) -> kv::Result<()> {
+        if !self.file().is_blank() {
+            self.flush_active()?;
+        }
+
+        let runs = self
+            .levels
+            .first()
+            .map(|runs| runs.len())
+            .unwrap_or_default();
+        if runs >= self.options.level0_runs {
+            self.merge_down(0, runs)?;
+        }
+
+        let mut level = 1;
+        while level < self.levels.len() {
+            let size: u64 = self.levels[level].iter().map(|run| run.size).sum();
+            let limit = match self.options.level_fanout.checked_pow(level as u32) {
+                Some(scale) => self.options.run_bytes.saturating_mul(scale),
+                None => u64::MAX,
+            };
+            if size > limit {
+                self.merge_down(level, 1)?;
+            } else {
+                level += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_tiered(&mut self) -> kv::Result<()> {
+        if !self.file().is_blank() {
+            self.flush_active()?;
+        }
+
+        let tiers = match self.levels.first() {
+            Some(runs) => runs,
+            None => return Ok(()),
+        };
+        let mut window = None;
+        for start in 0..tiers.len() {
+            let mut total = tiers[start].size;
+            let mut end = start + 1;
+            while end < tiers.len() {
+                let mean = total / (end - start) as u64;
+                let size = tiers[end].size;
+                if size < mean / 2 || size > mean + mean / 2 {
+                    break;
+                }
+                total += size;
+                end += 1;
+            }
+            if end - start >= self.options.tier_min_runs {
+                window = Some((start, end));
+                break;
+            }
+        }
+
+        if let Some((start, end)) = window {
+            let ids: Vec<FileId> = tiers[start..end].iter().map(|run| run.id).collect();
+            let mut srcs = self.open_inputs(&ids)?;
+            let input = data_bytes(&srcs)?;
+
+            let runs = self.write_runs(&mut srcs, &ids, input, u64::MAX, start == 0)?;
+            self.levels[0].splice(start..end, runs);
+
+            self.remove_files(&ids)?;
+        }
+        Ok(())
+    }
+
+    /// Sorts the active log into new runs at the first level and moves on to
+    /// a new log. The old one is only deleted once the runs are synced and
+    /// in the manifest, so that a crash at any point loses nothing.
+    fn flush_active(&mut self) -> kv::Result<()> {
+        let old = self.id;
+        let dir = self.id_to_dir_path(&old);
+        let (names, order) = (&self.options.names, &self.options.comparator);
+        let throttle = Throttle::new(None);
+        let file = self.files.get_mut(&old).unwrap();
+        // Taken before the split, whose chunks leave out the records that
+        // range tombstones cover.
+        let input = file.offset - file.start;
+        let limit = self.options.run_bytes as usize;
+        let runs = split(file, &dir, names, limit, order, &throttle)
+            .map_err(kv::Error::from)
+            .and_then(|mut chunks| self.write_runs(&mut chunks, &[old], input, u64::MAX, false));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        let runs = runs?;
+
+        let id = FileId(self.next);
+        self.next += 1;
+        self.files.insert(id, self.id_to_file(&id)?);
+        self.id = id;
+        self.size_buffer();
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        self.levels[0].extend(runs);
+        self.remove_files(&[old])
+    }
+
+    /// Merges the first `count` runs of level `from` with the runs they
+    /// overlap one level down. The levels only change once the merged runs
+    /// are written, so a failed merge leaves them as they were.
+    fn merge_down(&mut self, from: usize, count: usize) -> kv::Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        let level = from + 1;
+        if self.levels.len() <= level {
+            self.levels.resize_with(level + 1, Vec::new);
+        }
+        let order = &self.options.comparator;
+        let upper = &self.levels[from][..count];
+        let min = upper
+            .iter()
+            .map(|run| &run.min)
+            .min_by(|a, b| order.compare(a, b));
+        let max = upper
+            .iter()
+            .map(|run| &run.max)
+            .max_by(|a, b| order.compare(a, b));
+        let (min, max) = (min.unwrap(), max.unwrap());
+        let lower = self.levels[level]
+            .iter()
+            .filter(|run| run.overlaps(order, min, max));
+        let ids: Vec<FileId> = lower.chain(upper).map(|run| run.id).collect();
+        let mut srcs = self.open_inputs(&ids)?;
+        let input = data_bytes(&srcs)?;
+
+        let bottom = self.levels[level + 1..].iter().all(|runs| runs.is_empty());
+        let limit = self.options.run_bytes;
+        let runs = self.write_runs(&mut srcs, &ids, input, limit, bottom)?;
+        let order = &self.options.comparator;
+        self.levels[from].drain(..count);
+        self.levels[level].retain(|run| !ids.contains(&run.id));
+        self.levels[level].extend(runs);
+        self.levels[level].sort_by(|a, b| order.compare(&a.min, &b.min));
+
+        self.remove_files(&ids)
+    }
+
+    fn write_runs(
+        &mut self,
+        srcs: &mut [StoreFile],
+        inputs: &[FileId],
+        input: u64,
+        limit: u64,
+        drop_tombstones: bool,
+    ) -> kv::Result<Vec<Run>> {
+        let mut runs: Vec<Run> = Vec::new();
+        let mut outs: Vec<StoreFile> = Vec::new();
+        let base = &self.base;
+        let names = &self.options.names;
+        let encoding = self.options.encoding();
+        let next = &mut self.next;
+        let index = &self.index;
+        let order = &self.options.comparator;
+        let mut throttle = Throttle::new(self.options.compaction_rate_limit);
+        // Applied to the index only once every run is written.
+        let mut moved = Vec::new();
+        let mut dropped = false;
+        let merged = merge_runs(srcs, order, |record| {
+            if drop_tombstones && record.val().is_none() {
+                dropped = true;
+                return Ok(());
+            }
+            // A run is only cut past the end of the ranges in it, so the
+            // runs written here never overlap.
+            let cut = match (outs.last(), runs.last()) {
+                (Some(out), Some(run)) => {
+                    out.offset >= limit && order.compare(record.key(), &run.max).is_gt()
+                }
+                _ => true,
+            };
+            if cut {
+                let id = FileId(*next);
+                *next += 1;
+                outs.push(StoreFile::make(id, names.data(base, &id), encoding)?);
+                runs.push(Run {
+                    id,
+                    min: record.key().to_vec(),
+                    max: record.end().to_vec(),
+                    size: 0,
+                });
+            }
+            let out = outs.last_mut().unwrap();
+            let offset = out.offset;
+            match &record {
+                Record::Insert(key, val) => {
+                    let entry = out.insert(key, val)?;
+                    if let Some(current) = index.get(key.as_slice()) {
+                        if inputs.contains(&current.file) {
+                            moved.push((key.clone(), entry));
+                        }
+                    }
+                }
+                Record::Remove(key) => out.remove(key)?,
+                Record::RemoveRange(start, end) => out.remove_range(start, end)?,
+            }
+            throttle.wrote(out.offset - offset)?;
+            let run = runs.last_mut().unwrap();
+            if order.compare(record.end(), &run.max).is_gt() {
+                run.max = record.end().to_vec();
+            }
+            run.size = out.offset;
+            Ok(())
+        });
+        let synced = merged.and_then(|_| outs.iter_mut().try_for_each(|out| out.sync()));
+        if let Err(e) = synced {
+            for out in outs.iter() {
+                std::fs::remove_file(self.id_to_dat_path(&out.id))?;
+            }
+            return Err(e.into());
+        }
+
+        if dropped {
+            self.compacted_at = now_millis();
+        }
+        let output = outs.iter().map(|out| out.offset - out.start).sum();
+        self.reclaim(input, output);
+        for (key, entry) in moved {
+            self.index.insert(key, entry);
+        }
+        for out in outs {
+            self.files.insert(out.id, out);
+        }
+        Ok(runs)
+    }
+
+    /// Rewrites the files holding dead records with keys in the range
+    /// without them. Superseded inserts go first, from every file, and the
+    /// point tombstones only once that is persisted: a tombstone must not be
+    /// gone while a value it hides is still on disk, or a crash in between
+    /// brings the key back.
+    pub fn compact_range(&mut self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> kv::Result<()> {
+        for tombstones in [false, true] {
+            let ids: Vec<FileId> = self.files.keys().copied().collect();
+            for id in ids {
+                let mut src = self.open_run(&id)?;
+                let mut dead = false;
+                while let Some((offset, record)) = src.next_with_offset() {
+                    if self.is_dead_in(&id, offset, &record, start, end, tombstones) {
+                        dead = true;
+                        break;
+                    }
+                }
+                if dead {
+                    self.rewrite_range(id, start, end, tombstones)?;
+                }
+            }
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Whether `compact_range` drops `record`: a superseded insert in the
+    /// range, or with `tombstones` a point tombstone in it. Range tombstones
+    /// are always kept.
+    fn is_dead_in(
+        &self,
+        id: &FileId,
+        offset: u64,
+        record: &Record,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        tombstones: bool,
+    ) -> bool {
+        if !self.options.comparator.contains(start, end, record.key()) {
+            return false;
+        }
+        match record.kind() {
+            RecordKind::Insert => !self.is_live(id, offset, record),
+            RecordKind::Remove => tombstones,
+            RecordKind::RemoveRange => false,
+        }
+    }
+
+    fn is_live(&self, id: &FileId, offset: u64, record: &Record) -> bool {
+        let encoding = match self.files.get(id) {
+            Some(file) => file.encoding,
+            None => return false,
+        };
+        match (record.val(), self.index.get(record.key())) {
+            (Some(val), Some(entry)) => {
+                let at = value_offset(encoding, offset, record.key(), val);
+                entry.file == *id && entry.offset == at
+            }
+            _ => false,
+        }
+    }
+
+    fn rewrite_range(
+        &mut self,
+        id: FileId,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        tombstones: bool,
+    ) -> kv::Result<()> {
+        let path = self.id_to_dat_path(&id);
+        let tmp = self.id_to_path(&id, ".tmp");
+        let mut src = self.open_run(&id)?;
+        let input = src.file.metadata()?.len();
+        let mut dst = StoreFile::make(id, &tmp, src.encoding)?;
+        let mut min: Option<Vec<u8>> = None;
+        let mut max: Option<Vec<u8>> = None;
+        while let Some((offset, record)) = src.next_with_offset() {
+            if self.is_dead_in(&id, offset, &record, start, end, tombstones) {
+                if record.kind() == RecordKind::Remove {
+                    self.compacted_at = now_millis();
+                }
+                continue;
+            }
+            let live = self.is_live(&id, offset, &record);
+            match &record {
+                Record::Insert(key, val) => {
+                    let entry = dst.insert(key, val)?;
+                    if live {
+                        self.index.insert(key.to_vec(), entry);
+                    }
+                }
+                Record::Remove(key) => dst.remove(key)?,
+                Record::RemoveRange(start, end) => dst.remove_range(start, end)?,
+            }
+            if min.is_none() {
+                min = Some(record.key().to_vec());
+            }
+            let order = &self.options.comparator;
+            if max
+                .as_deref()
+                .map(|max| order.compare(record.end(), max).is_ge())
+                .unwrap_or(true)
+            {
+                max = Some(record.end().to_vec());
+            }
+        }
+        dst.sync()?;
+        std::fs::rename(&tmp, &path)?;
+        manifest::sync_dir(&path)?;
+        self.reclaim(input, dst.offset);
+        self.files.insert(id, dst);
+
+        for runs in self.levels.iter_mut() {
+            if let Some(pos) = runs.iter().position(|run| run.id == id) {
+                match (min, max) {
+                    (Some(min), Some(max)) => {
+                        let run = &mut runs[pos];
+                        run.min = min;
+                        run.max = max;
+                        run.size = self.files[&id].offset;
+                    }
+                    _ => {
+                        runs.remove(pos);
+                        self.remove_files(&[id])?;
+                    }
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn reclaim(&mut self, input: u64, output: u64) {
+        self.dead_bytes = self.dead_bytes.saturating_sub(input.saturating_sub(output));
     }
 
-    impl From<std::io::Error> for Error {
-        fn from(e: std::io::Error) -> Self {
-            Self::IO(e)
+    fn open_run(&self, id: &FileId) -> kv::Result<StoreFile> {
+        let mut file = self.id_to_file(id)?;
+        file.reset()?;
+        Ok(file)
+    }
+
+    fn open_inputs(&self, ids: &[FileId]) -> kv::Result<Vec<StoreFile>> {
+        let mut srcs = Vec::with_capacity(ids.len());
+        for id in ids {
+            srcs.push(self.open_run(id)?);
         }
+        Ok(srcs)
     }
 
-    pub type Result<T> = std::result::Result<T, Error>;
+    /// Drops `ids` from the store, persisting the layout without them before
+    /// deleting them, so that a crash in between only leaves files that
+    /// `open` ignores.
+    fn remove_files(&mut self, ids: &[FileId]) -> kv::Result<()> {
+        for id in ids {
+            self.files.remove(id);
+        }
+        self.persist()?;
+        for id in ids {
+            std::fs::remove_file(self.id_to_dat_path(id))?;
+        }
+        Ok(())
+    }
+
+    fn open_runs(&self) -> kv::Result<Vec<StoreFile>> {
+        let mut srcs = Vec::new();
+        for runs in self.levels.iter().rev() {
+            for run in runs {
+                srcs.push(self.open_run(&run.id)?);
+            }
+        }
+        Ok(srcs)
+    }
+
+    fn size_buffer(&mut self) {
+        let bytes = self.options.write_buffer_bytes;
+        self.file().buffer_bytes = bytes;
+    }
+
+    /// The file writes go to. `reduce` moves it to a fresh id, so resolve it
+    /// here rather than holding on to an id across compactions. There is no
+    /// generation to check: every compaction takes `&mut self`, and every
+    /// handle into the files (this one, an `Iter`, `iter_raw`) borrows the
+    /// store, so none of them can be alive when the files are swapped. What
+    /// a caller can keep, a `RecordLocation`, is only a number: reads go
+    /// through the index, which the compaction updates before it returns.
+    pub fn file(&mut self) -> &mut StoreFile {
+        self.files.get_mut(&self.id).unwrap()
+    }
 }
 
-struct IndexEntry {
-    file: FileId,
-    offset: u64,
-    length: u64,
+/// Writes out whatever is still buffered; errors are lost here, so call
+/// `flush` or `sync` to see them.
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Some(file) = self.files.get_mut(&self.id) {
+            let _ = file.flush();
+        }
+    }
 }
 
-pub struct Store {
+pub struct Iter<'a> {
+    entries: Entries<'a>,
+    store: &'a Store,
+}
+
+/// Index entries in key order: a range of the index itself, or of the keys
+/// ordered by a comparator other than the byte order, looked up in it.
+enum Entries<'a> {
+    Empty,
+    Index(btree_map::Range<'a, Vec<u8>, RecordLocation>),
+    Ordered(OrderedRange<'a>, &'a Index),
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (&'a Vec<u8>, &'a RecordLocation);
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Entries::Empty => None,
+            Entries::Index(entries) => entries.next(),
+            Entries::Ordered(keys, index) => keys.next().and_then(|key| index.get_key_value(key)),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Entries<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Entries::Empty => None,
+            Entries::Index(entries) => entries.next_back(),
+            Entries::Ordered(keys, index) => {
+                keys.next_back().and_then(|key| index.get_key_value(key))
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = kv::Result<(Vec<u8>, Vec<u8>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, entry) = self.entries.next()?;
+        let mut val = Vec::new();
+        let read = self.store.read_value_into(key, entry, &mut val);
+        Some(read.map(|_| (key.clone(), val)))
+    }
+}
+
+impl<'a> IntoIterator for &'a Store {
+    type Item = kv::Result<Entry>;
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Store {
+    type Item = kv::Result<Entry>;
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct StoreFile {
     id: FileId,
-    base: PathBuf,
-    files: BTreeMap<FileId, StoreFile>,
-    index: BTreeMap<Vec<u8>, IndexEntry>,
+    file: File,
+    offset: u64,
+    start: u64,
+    encoding: Encoding,
+    ranges: bool,
+    recent_peek: Option<Record>,
+    /// Holds the key read by `peek_key`.
+    key_buffer: Vec<u8>,
+    buffer: Vec<u8>,
+    buffer_bytes: usize,
+    /// Written to since the last `sync`.
+    dirty: bool,
 }
 
-impl Store {
-    pub fn open(base: &str) -> kv::Result<Self> {
-        // TODO:
-        // 1. Scan base dir (report error if the dir is missing)
-        // 2. Build index from data files
-        // 3. Compact files (in background)
-        // 4. Create a new file
+/// The decoded header of a record.
+struct RecordHead {
+    op: u64,
+    key_len: u64,
+    /// `None` for a point tombstone, which has no value.
+    val_len: Option<u64>,
+    /// Where the key starts.
+    at: u64,
+}
 
-        let id = FileId(1);
-        let mut this = Self {
-            id,
-            base: PathBuf::from(base),
-            files: BTreeMap::default(),
-            index: BTreeMap::default(),
-        };
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FileId(u64);
 
-        this.files.insert(id, this.id_to_file(&id)?);
+impl FileId {
+    pub fn new(id: u64) -> Self {
+        Self(id)
+    }
 
-        Ok(this)
+    pub fn get(&self) -> u64 {
+        self.0
     }
+}
 
-    pub fn insert(&mut self, key: &[u8], val: &[u8]) -> kv::Result<()> {
-        let entry = self.files.get_mut(&self.id).unwrap().insert(key, val)?;
-        self.index.insert(key.to_vec(), entry);
-        Ok(())
+/// Formats as the stem of the file's name.
+impl std::fmt::Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:020}", self.0)
     }
+}
 
-    pub fn remove(&mut self, key: &[u8]) -> kv::Result<bool> {
-        self.files.get_mut(&self.id).unwrap().remove(key)?;
-        Ok(self.index.remove(key).is_some())
+/// Fills `field`, or returns `false` if `src` is already at its end.
+fn read_field(src: &mut impl Read, field: &mut [u8; FIELD_LEN]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < FIELD_LEN {
+        match src.read(&mut field[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            n => filled += n,
+        }
     }
+    Ok(true)
+}
 
-    pub fn lookup(&mut self, key: &[u8]) -> kv::Result<Option<Vec<u8>>> {
-        if let Some(IndexEntry {
-            file,
-            offset,
-            length,
-        }) = self.index.get(key)
-        {
-            if !self.files.contains_key(file) {
-                self.files.insert(*file, self.id_to_file(file)?);
-            }
-            let mut buffer = vec![0u8; *length as usize];
-            self.files
-                .get_mut(file)
-                .unwrap()
-                .read(*offset, &mut buffer[..])?;
-            return Ok(Some(buffer));
+/// Reads one field, or returns `None` if `src` is already at its end.
+fn read_int(src: &mut impl Read, encoding: Encoding) -> io::Result<Option<u64>> {
+    let mut buf = [0u8; format::MAX_VARINT_LEN];
+    let mut filled = 0;
+    loop {
+        if let Some((val, _)) = encoding.get(&buf[..filled]) {
+            return Ok(Some(val));
+        }
+        let want = match encoding {
+            Encoding::Fixed(_) => FIELD_LEN,
+            Encoding::Varint => filled + 1,
+        };
+        if want > buf.len() {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+        match src.read(&mut buf[filled..want])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            n => filled += n,
         }
-        Ok(None)
     }
+}
 
-    fn id_to_dir_path(&self, id: &FileId) -> impl AsRef<Path> {
-        self.id_to_path(id, "")
+/// Reads the rest of a record after its op code. Key and value buffers grow
+/// with the bytes actually read, so a corrupt length cannot force a huge
+/// allocation up front.
+fn read_log_record(
+    src: &mut impl Read,
+    encoding: Encoding,
+    ranges: bool,
+    op: u64,
+) -> io::Result<Record> {
+    fn len(src: &mut impl Read, encoding: Encoding) -> io::Result<u64> {
+        read_int(src, encoding)?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+    fn bytes(src: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        if src.take(len).read_to_end(&mut buf)? as u64 != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(buf)
+    }
+    match op {
+        INSERT => {
+            let key_len = len(src, encoding)?;
+            let val_len = len(src, encoding)?;
+            let key = bytes(src, key_len)?;
+            let val = bytes(src, val_len)?;
+            Ok(Record::Insert(key, val))
+        }
+        REMOVE => {
+            let key_len = len(src, encoding)?;
+            Ok(Record::Remove(bytes(src, key_len)?))
+        }
+        REMOVE_RANGE if ranges => {
+            let start_len = len(src, encoding)?;
+            let end_len = len(src, encoding)?;
+            let start = bytes(src, start_len)?;
+            let end = bytes(src, end_len)?;
+            Ok(Record::RemoveRange(start, end))
+        }
+        _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
     }
+}
 
-    fn id_to_dat_path(&self, id: &FileId) -> impl AsRef<Path> {
-        self.id_to_path(id, ".dat")
+/// The first key past every key starting with `prefix`, or `None` if there
+/// is none (the prefix is empty or all `0xFF`).
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
     }
+    None
+}
+
+/// Where the value of an insert of `key` and `val` written at `offset`
+/// starts: past the op code, both lengths and the key.
+fn value_offset(encoding: Encoding, offset: u64, key: &[u8], val: &[u8]) -> u64 {
+    offset + (encoding.insert_header_len(key.len(), val.len()) + key.len()) as u64
+}
+
+fn now_millis() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_millis() as u64
+}
+
+/// How the files of a store are named: data files as
+/// `{prefix}{id:020}.{extension}`, next to `{prefix}MANIFEST` and
+/// `{prefix}INDEX`.
+#[derive(Clone, Debug)]
+struct FileNames {
+    prefix: String,
+    extension: String,
+}
 
-    fn id_to_path(&self, id: &FileId, extension: &str) -> impl AsRef<Path> {
-        let name = format!("{:020}{}", id.0, extension);
-        let mut path = self.base.clone();
-        path.push(&name);
-        path
+impl Default for FileNames {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            extension: "dat".to_string(),
+        }
     }
+}
 
-    fn id_to_file(&self, id: &FileId) -> kv::Result<StoreFile> {
-        let file = StoreFile::open(*id, self.id_to_path(id, ".dat"))?;
-        Ok(file)
+impl FileNames {
+    fn is_valid(&self) -> bool {
+        let plain = |name: &str| !name.contains(std::path::is_separator);
+        let reserved = ["", "tmp", "reduce"].contains(&self.extension.as_str());
+        plain(&self.prefix) && plain(&self.extension) && !reserved
     }
 
-    pub fn len(&self) -> usize {
-        self.index.len()
+    fn parse(&self, name: &str) -> Option<FileId> {
+        self.parse_with(name, &format!(".{}", self.extension))
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    /// Whether `name` is left over from an interrupted compaction: a split
+    /// directory, a `reduce` output not swapped in yet or a file being
+    /// rewritten.
+    fn is_scratch(&self, name: &str) -> bool {
+        ["", ".reduce", ".tmp"]
+            .iter()
+            .any(|extension| self.parse_with(name, extension).is_some())
     }
 
-    pub fn reduce(&mut self, limit: usize) -> kv::Result<()> {
-        let path = self.id_to_dat_path(&self.id);
-        let file = self.files.get_mut(&self.id).unwrap();
+    fn parse_with(&self, name: &str, extension: &str) -> Option<FileId> {
+        let id = name
+            .strip_prefix(self.prefix.as_str())?
+            .strip_suffix(extension)?;
+        if id.len() != 20 || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        id.parse().ok().map(FileId)
+    }
 
-        let mut chunks = split(file, &self.base, limit)?;
-        *file = StoreFile::make(self.id, &path)?;
-        self.index = merge(file, &mut chunks)?;
+    fn data(&self, base: &Path, id: &FileId) -> PathBuf {
+        self.path(base, id, &format!(".{}", self.extension))
+    }
 
-        let path = self.id_to_dir_path(&self.id);
-        std::fs::remove_dir_all(&path)?;
-        Ok(())
+    fn path(&self, base: &Path, id: &FileId, extension: &str) -> PathBuf {
+        base.join(format!("{}{}{}", self.prefix, id, extension))
     }
 
-    pub fn file(&mut self) -> &mut StoreFile {
-        self.files.get_mut(&self.id).unwrap()
+    fn manifest(&self, base: &Path) -> PathBuf {
+        base.join(format!("{}{}", self.prefix, manifest::NAME))
     }
-}
 
-pub struct StoreFile {
-    id: FileId,
-    file: File,
-    offset: u64,
-    recent_peek: Option<Record>,
+    fn checkpoint(&self, base: &Path) -> PathBuf {
+        base.join(format!("{}{}", self.prefix, checkpoint::NAME))
+    }
 }
 
-const INSERT: u64 = 1;
-const REMOVE: u64 = 2;
-
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
-pub struct FileId(u64);
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum RecordKind {
+    Insert,
+    Remove,
+    RemoveRange,
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Record {
     Insert(Vec<u8>, Vec<u8>),
     Remove(Vec<u8>),
+    /// Removes every key in `[start, end)` written before it.
+    RemoveRange(Vec<u8>, Vec<u8>),
+}
+
+/// By key bytes, then kind (insert, remove, range removal), then value or
+/// range end. This is not the order records are merged in: there, equal keys
+/// keep the order they were written in and keys follow the store's
+/// comparator.
+impl Ord for Record {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let (a, b) = (self, other);
+        a.key()
+            .cmp(b.key())
+            .then(a.kind().cmp(&b.kind()))
+            .then_with(|| (a.val(), a.end()).cmp(&(b.val(), b.end())))
+    }
+}
+
+impl PartialOrd for Record {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl Record {
+    pub fn kind(&self) -> RecordKind {
+        match self {
+            Record::Insert(..) => RecordKind::Insert,
+            Record::Remove(_) => RecordKind::Remove,
+            Record::RemoveRange(..) => RecordKind::RemoveRange,
+        }
+    }
+
+    pub fn is_insert(&self) -> bool {
+        self.kind() == RecordKind::Insert
+    }
+
+    /// True for tombstones of either kind.
+    pub fn is_remove(&self) -> bool {
+        !self.is_insert()
+    }
+
+    /// The key the record sorts by: the start of a range tombstone.
     pub fn key(&self) -> &[u8] {
         match self {
             Record::Insert(key, _) => key,
             Record::Remove(key) => key,
+            Record::RemoveRange(start, _) => start,
         }
     }
 
+    /// `None` only for tombstones: an insert of an empty value is
+    /// `Some(&[])` and keeps its key present through every compaction, so
+    /// test for a delete with this, never with the value's length.
     pub fn val(&self) -> Option<&[u8]> {
         match self {
             Record::Insert(_, val) => Some(val),
-            Record::Remove(_) => None,
+            Record::Remove(_) | Record::RemoveRange(..) => None,
+        }
+    }
+
+    /// The last key the record covers: its key, or the (exclusive) end of a
+    /// range tombstone.
+    pub fn end(&self) -> &[u8] {
+        match self {
+            Record::RemoveRange(_, end) => end,
+            record => record.key(),
         }
     }
 
+    /// Size of the record in a file of fixed-width fields.
     pub fn len(&self) -> usize {
+        self.encoded_len(Encoding::default())
+    }
+
+    /// Size of the record on disk in a file of the given encoding. Must always
+    /// equal the number of bytes the write path produces for it:
+    /// `peek_record` rewinds by exactly this much.
+    pub fn encoded_len(&self, encoding: Encoding) -> usize {
         match self {
-            Record::Insert(key, val) => 8 + 8 + key.len() + 8 + val.len(),
-            Record::Remove(key) => 8 + 8 + key.len(),
+            Record::Insert(key, val) => encoding.insert_len(key.len(), val.len()),
+            Record::Remove(key) => encoding.remove_len(key.len()),
+            Record::RemoveRange(start, end) => encoding.remove_range_len(start.len(), end.len()),
         }
     }
 
+    /// True when the record carries no payload: an empty key and no (or an
+    /// empty) value. The on-disk size of a record is never zero. This says
+    /// nothing about whether the record is a delete; see `val`.
     pub fn is_empty(&self) -> bool {
-        self.len() == 0
+        self.key().is_empty() && self.val().unwrap_or_default().is_empty()
     }
 }
 
 impl StoreFile {
-    fn create(id: FileId, path: impl AsRef<Path>, truncate: bool) -> io::Result<Self> {
+    fn create(
+        id: FileId,
+        path: impl AsRef<Path>,
+        truncate: bool,
+        encoding: Encoding,
+    ) -> io::Result<Self> {
         let file = OpenOptions::new()
             .create(true)
             .truncate(truncate)
             .write(true)
             .read(true)
             .open(&path)?;
-        let offset = file.metadata()?.len() as u64;
+        let len = file.metadata()?.len();
+        let (encoding, ranges, start) = if len == 0 {
+            file.write_all_at(&format::file_header(encoding, false), 0)?;
+            (encoding, false, FILE_HEADER_LEN as u64)
+        } else {
+            let mut header = [0u8; FILE_HEADER_LEN];
+            let found = match file.read_exact_at(&mut header, 0) {
+                Ok(()) => format::parse_file_header(&header)?,
+                Err(_) => None,
+            };
+            match found {
+                Some((encoding, ranges)) => (encoding, ranges, FILE_HEADER_LEN as u64),
+                None => (Encoding::default(), false, 0),
+            }
+        };
         Ok(Self {
             id,
             file,
-            offset,
+            offset: len.max(start),
+            start,
+            encoding,
+            ranges,
             recent_peek: None,
+            key_buffer: Vec::new(),
+            buffer: Vec::new(),
+            buffer_bytes: WRITE_BUFFER_BYTES,
+            dirty: len == 0,
         })
     }
 
-    fn open(id: FileId, path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::create(id, path, false)
+    fn open(id: FileId, path: impl AsRef<Path>, encoding: Encoding) -> io::Result<Self> {
+        Self::create(id, path, false, encoding)
+    }
+
+    fn make(id: FileId, path: impl AsRef<Path>, encoding: Encoding) -> io::Result<Self> {
+        Self::create(id, path, true, encoding)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
     }
 
-    fn make(id: FileId, path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::create(id, path, true)
+    /// Byte order of the record fields; varint files have none and report
+    /// the default.
+    pub fn byte_order(&self) -> ByteOrder {
+        match self.encoding {
+            Encoding::Fixed(order) => order,
+            Encoding::Varint => ByteOrder::default(),
+        }
     }
 
-    fn insert(&mut self, key: &[u8], val: &[u8]) -> io::Result<IndexEntry> {
-        let key_len = key.len() as u64;
-        let val_len = val.len() as u64;
-        self.file.seek(SeekFrom::Start(self.offset))?;
-        self.file.write_all(&INSERT.to_be_bytes())?;
-        self.file.write_all(&key_len.to_be_bytes())?;
-        self.file.write_all(&val_len.to_be_bytes())?;
-        self.file.write_all(key)?;
-        self.file.write_all(val)?;
-        self.file.flush()?;
+    fn is_blank(&self) -> bool {
+        self.offset <= self.start
+    }
 
-        self.offset += std::mem::size_of::<u64>() as u64 * 3 + key_len + val_len;
-        let offset = self.offset - val_len;
+    fn insert(&mut self, key: &[u8], val: &[u8]) -> io::Result<RecordLocation> {
+        let start = self.buffer.len();
+        self.encoding.put(&mut self.buffer, INSERT);
+        self.encoding.put(&mut self.buffer, key.len() as u64);
+        self.encoding.put(&mut self.buffer, val.len() as u64);
+        self.buffer.extend_from_slice(key);
+        self.buffer.extend_from_slice(val);
+
+        let offset = value_offset(self.encoding, self.offset, key, val);
+        self.offset += (self.buffer.len() - start) as u64;
+        if self.buffer.len() >= self.buffer_bytes {
+            self.flush()?;
+        }
 
-        Ok(IndexEntry {
+        Ok(RecordLocation {
             file: self.id,
             offset,
-            length: val_len,
+            length: val.len() as u64,
         })
     }
 
     fn remove(&mut self, key: &[u8]) -> io::Result<()> {
-        let key_len = key.len() as u64;
-        self.file.seek(SeekFrom::Start(self.offset))?;
-        self.file.write_all(&REMOVE.to_be_bytes())?;
-        self.file.write_all(&key_len.to_be_bytes())?;
-        self.file.write_all(key)?;
-        self.file.flush()?;
+        let start = self.buffer.len();
+        self.encoding.put(&mut self.buffer, REMOVE);
+        self.encoding.put(&mut self.buffer, key.len() as u64);
+        self.buffer.extend_from_slice(key);
+
+        self.offset += (self.buffer.len() - start) as u64;
+        if self.buffer.len() >= self.buffer_bytes {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Range tombstones need a file of `RANGE_VERSION`: the header of this one
+    /// is upgraded in place before the first, which headerless files cannot
+    /// take.
+    fn remove_range(&mut self, start: &[u8], end: &[u8]) -> io::Result<()> {
+        if !self.ranges {
+            if self.start == 0 {
+                return Err(io::Error::from(io::ErrorKind::Unsupported));
+            }
+            self.file
+                .write_all_at(&format::file_header(self.encoding, true), 0)?;
+            self.ranges = true;
+            self.dirty = true;
+        }
+        let at = self.buffer.len();
+        self.encoding.put(&mut self.buffer, REMOVE_RANGE);
+        self.encoding.put(&mut self.buffer, start.len() as u64);
+        self.encoding.put(&mut self.buffer, end.len() as u64);
+        self.buffer.extend_from_slice(start);
+        self.buffer.extend_from_slice(end);
+
+        self.offset += (self.buffer.len() - at) as u64;
+        if self.buffer.len() >= self.buffer_bytes {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
 
-        let length = std::mem::size_of::<u64>() as u64 * 2 + key_len;
-        self.offset += length;
+    /// Writes out records buffered by `insert`/`remove`; they are readable
+    /// through positional reads even before that, but not through a second
+    /// handle to the same file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let at = self.offset - self.buffer.len() as u64;
+        self.file.write_all_at(&self.buffer, at)?;
+        self.buffer.clear();
+        self.dirty = true;
+        Ok(())
+    }
 
+    /// Flushes and waits for the file to be on disk, unless nothing was
+    /// written since the last call.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if self.dirty {
+            self.file.sync_all()?;
+            self.dirty = false;
+        }
         Ok(())
     }
 
     fn exec(&mut self, record: &Record) -> io::Result<()> {
+        let offset = self.offset;
         match record {
             Record::Insert(key, val) => {
                 self.insert(key, val)?;
-                Ok(())
             }
             Record::Remove(key) => {
                 self.remove(key)?;
-                Ok(())
+            }
+            Record::RemoveRange(start, end) => {
+                self.remove_range(start, end)?;
             }
         }
+        debug_assert_eq!(
+            self.offset - offset,
+            record.encoded_len(self.encoding) as u64
+        );
+        Ok(())
+    }
+
+    /// Writes `record` at the end of the file and returns where it starts.
+    /// On the active file of a store, the index only picks it up once the
+    /// store is reopened.
+    pub fn append_record(&mut self, record: &Record) -> io::Result<u64> {
+        let offset = self.offset;
+        self.exec(record)?;
+        Ok(offset)
+    }
+
+    /// Whether `length` bytes at `offset` lie within the records of the file,
+    /// past the header and before the end.
+    fn holds(&self, offset: u64, length: u64) -> io::Result<bool> {
+        let end = match offset.checked_add(length) {
+            Some(end) if offset >= self.start => end,
+            _ => return Ok(false),
+        };
+        Ok(end <= self.offset || end <= self.file.metadata()?.len())
     }
 
-    fn read(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        if !self.holds(offset, buffer.len() as u64)? {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        let flushed = self.offset - self.buffer.len() as u64;
+        if !self.buffer.is_empty() && offset >= flushed {
+            let at = (offset - flushed) as usize;
+            let src = self
+                .buffer
+                .get(at..at + buffer.len())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            buffer.copy_from_slice(src);
+            return Ok(());
+        }
         self.file.read_exact_at(buffer, offset)
     }
 
     pub fn read_record(&mut self) -> io::Result<Record> {
+        self.flush()?;
         if let Some(record) = self.recent_peek.take() {
-            self.offset += record.len() as u64;
+            self.offset += record.encoded_len(self.encoding) as u64;
             return Ok(record);
         }
-        let mut buf = [0u8; 8];
-        self.file.read_exact_at(&mut buf[..], self.offset)?;
-        let op = u64::from_be_bytes(buf);
-
-        self.file.read_exact_at(&mut buf[..], self.offset + 8)?;
-        let key_len = u64::from_be_bytes(buf);
-
-        // TODO Add sanity check for max key/value length
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let RecordHead {
+            op,
+            key_len,
+            val_len,
+            at,
+        } = self.read_head()?.ok_or_else(eof)?;
+        let val_len = val_len.unwrap_or_default();
+        self.check_len(at, key_len.checked_add(val_len))?;
+        let mut buf = vec![0u8; (key_len + val_len) as usize];
+        self.file.read_exact_at(&mut buf[..], at)?;
+        self.offset = at + key_len + val_len;
+
+        let val = buf.split_off(key_len as usize);
         match op {
-            INSERT => {
-                self.file.read_exact_at(&mut buf[..], self.offset + 16)?;
-                let val_len = u64::from_be_bytes(buf);
+            INSERT => Ok(Record::Insert(buf, val)),
+            REMOVE_RANGE => Ok(Record::RemoveRange(buf, val)),
+            _ => Ok(Record::Remove(buf)),
+        }
+    }
+
+    /// The key of the next record, read without its value into a buffer
+    /// that is reused across calls; `None` at the end of the file.
+    pub fn peek_key(&mut self) -> io::Result<Option<&[u8]>> {
+        self.flush()?;
+        if let Some(record) = &self.recent_peek {
+            return Ok(Some(record.key()));
+        }
+        let RecordHead { key_len, at, .. } = match self.read_head()? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        self.check_len(at, Some(key_len))?;
+        self.key_buffer.resize(key_len as usize, 0);
+        self.file.read_exact_at(&mut self.key_buffer, at)?;
+        Ok(Some(&self.key_buffer))
+    }
 
-                let mut buf = vec![0u8; (key_len + val_len) as usize];
-                self.file.read_exact_at(&mut buf[..], self.offset + 24)?;
-                self.offset += 24 + key_len + val_len;
+    /// Moves past the next record from its header alone, unless it is a range
+    /// tombstone, and returns whether it did.
+    fn skip_unless_range(&mut self) -> io::Result<bool> {
+        self.flush()?;
+        if let Some(record) = self.recent_peek.take() {
+            if record.kind() == RecordKind::RemoveRange {
+                self.recent_peek = Some(record);
+                return Ok(false);
+            }
+            self.offset += record.encoded_len(self.encoding) as u64;
+            return Ok(true);
+        }
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let RecordHead {
+            op,
+            key_len,
+            val_len,
+            at,
+        } = self.read_head()?.ok_or_else(eof)?;
+        if op == REMOVE_RANGE {
+            return Ok(false);
+        }
+        let len = key_len.checked_add(val_len.unwrap_or_default());
+        match len {
+            Some(len) if self.holds(at, len)? => self.offset = at + len,
+            _ => return Err(eof()),
+        }
+        Ok(true)
+    }
 
-                let val = buf.split_off(key_len as usize);
-                Ok(Record::Insert(buf, val))
+    /// Decodes the header of the record at the read position; `None` if the
+    /// file ends right there.
+    fn read_head(&self) -> io::Result<Option<RecordHead>> {
+        let mut head = [0u8; 3 * format::MAX_VARINT_LEN];
+        let head = &mut head[..self.encoding.max_header_len()];
+        let mut filled = 0;
+        while filled < head.len() {
+            match self
+                .file
+                .read_at(&mut head[filled..], self.offset + filled as u64)?
+            {
+                0 => break,
+                n => filled += n,
             }
-            REMOVE => {
-                let mut buf = vec![0u8; key_len as usize];
-                self.file.read_exact_at(&mut buf[..], self.offset + 16)?;
-                self.offset += 16 + key_len;
-                Ok(Record::Remove(buf))
+        }
+        if filled == 0 {
+            return Ok(None);
+        }
+        let head = &head[..filled];
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let (op, op_len) = self.encoding.get(head).ok_or_else(eof)?;
+        let (key_len, key_len_len) = self.encoding.get(&head[op_len..]).ok_or_else(eof)?;
+        let at = op_len + key_len_len;
+
+        match op {
+            INSERT | REMOVE_RANGE if op == INSERT || self.ranges => {
+                let (val_len, val_len_len) = self.encoding.get(&head[at..]).ok_or_else(eof)?;
+                Ok(Some(RecordHead {
+                    op,
+                    key_len,
+                    val_len: Some(val_len),
+                    at: self.offset + (at + val_len_len) as u64,
+                }))
             }
+            REMOVE => Ok(Some(RecordHead {
+                op,
+                key_len,
+                val_len: None,
+                at: self.offset + at as u64,
+            })),
             _ => Err(std::io::Error::from(std::io::ErrorKind::Unsupported)),
         }
     }
 
+    fn check_len(&self, at: u64, len: Option<u64>) -> io::Result<()> {
+        let fits = match len.and_then(|len| at.checked_add(len)) {
+            Some(end) => end - at < CHECKED_LEN || end <= self.file.metadata()?.len(),
+            None => false,
+        };
+        if fits {
+            Ok(())
+        } else {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+        }
+    }
+
+    /// Whether any complete record can be decoded past `offset`. Nearly any
+    /// bytes decode as varints, so in a varint file such a record only counts
+    /// if the records from it on line up exactly with the end of the file;
+    /// the last of those ends right at it, so that is what gets checked.
+    /// The rest of the file is read a chunk at a time.
+    fn valid_after(&self, offset: u64) -> io::Result<bool> {
+        let len = self.file.metadata()?.len().saturating_sub(offset);
+        let encoding = self.encoding;
+        let ranges = self.ranges;
+        // The end of the record whose header starts `src`, read at `at`.
+        let next = |src: &[u8], at: u64| -> Option<u64> {
+            let (op, op_len) = encoding.get(src)?;
+            let (key_len, mut end) = encoding.get(&src[op_len..])?;
+            end += op_len;
+            let body = match op {
+                INSERT | REMOVE_RANGE if op == INSERT || ranges => {
+                    let (val_len, val_len_len) = encoding.get(&src[end..])?;
+                    end += val_len_len;
+                    val_len.checked_add(key_len)?
+                }
+                REMOVE => key_len,
+                _ => return None,
+            };
+            let end = body.checked_add(at + end as u64)?;
+            (end <= len).then_some(end)
+        };
+        let head_len = encoding.max_header_len();
+        let mut chunk = vec![0u8; READ_CHUNK_BYTES + head_len];
+        let mut from = 1;
+        while from < len {
+            let to = len.min(from + READ_CHUNK_BYTES as u64);
+            let filled = (len - from).min((to - from) + head_len as u64);
+            let chunk = &mut chunk[..filled as usize];
+            self.file.read_exact_at(chunk, offset + from)?;
+            for at in from..to {
+                let end = next(&chunk[(at - from) as usize..], at);
+                let valid = match encoding {
+                    Encoding::Fixed(_) => end.is_some(),
+                    Encoding::Varint => end == Some(len),
+                };
+                if valid {
+                    return Ok(true);
+                }
+            }
+            from = to;
+        }
+        Ok(false)
+    }
+
+    fn next_with_offset(&mut self) -> Option<(u64, Record)> {
+        let offset = self.offset;
+        self.read_record().ok().map(|record| (offset, record))
+    }
+
     pub fn peek_record(&mut self) -> io::Result<&Record> {
-        if self.recent_peek.is_some() {
-            return Ok(self.recent_peek.as_ref().unwrap());
+        if self.recent_peek.is_none() {
+            let record = self.read_record()?;
+            self.offset -= record.encoded_len(self.encoding) as u64;
+            self.recent_peek = Some(record);
         }
-        let record = self.read_record()?;
-        self.offset -= record.len() as u64;
-        self.recent_peek = Some(record);
         Ok(self.recent_peek.as_ref().unwrap())
     }
 
+    pub fn records_of(&mut self, kind: RecordKind) -> impl Iterator<Item = Record> + '_ {
+        self.filter(move |record| record.kind() == kind)
+    }
+
     pub fn reset(&mut self) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(0))?;
-        self.offset = 0;
+        self.flush()?;
+        self.file.seek(SeekFrom::Start(self.start))?;
+        self.offset = self.start;
         Ok(())
     }
 
+    /// Counts the records in the file, leaving the read position as it was.
+    pub fn record_count(&mut self) -> io::Result<usize> {
+        let offset = self.offset;
+        let peek = self.recent_peek.take();
+        self.reset()?;
+        let count = self.by_ref().count();
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.recent_peek = peek;
+        Ok(count)
+    }
+
     pub fn unset(&mut self) -> io::Result<()> {
+        self.flush()?;
         self.offset = self.file.metadata()?.len();
         self.file.seek(SeekFrom::End(0))?;
         Ok(())
     }
+
+    /// Cuts the file at `offset`, which must be a record boundary past the
+    /// header, and continues reading and writing from there.
+    pub fn truncate(&mut self, offset: u64) -> io::Result<()> {
+        if offset < self.start {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.flush()?;
+        self.file.set_len(offset)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.recent_peek = None;
+        self.dirty = true;
+        Ok(())
+    }
 }
 
 impl Iterator for StoreFile {
@@ -323,45 +3498,42 @@ impl Iterator for StoreFile {
     }
 }
 
+/// Cuts `src` into sorted chunks of about `split_size_bytes`, written to
+/// files in `dir`. Only the cancel flag of `throttle` applies, checked
+/// before each chunk.
 fn split(
     src: &mut StoreFile,
-    base: impl AsRef<Path>,
+    dir: &Path,
+    names: &FileNames,
     split_size_bytes: usize,
+    order: &Comparator,
+    throttle: &Throttle,
 ) -> io::Result<Vec<StoreFile>> {
-    let dir = format!("{:020}", src.id.0);
-    let mut path: PathBuf = base.as_ref().to_path_buf();
-    path.push(dir);
-    std::fs::create_dir_all(&path)?;
+    std::fs::create_dir_all(dir)?;
 
     let mut result = Vec::new();
     let mut records = Vec::new();
     let mut idx = 0;
     let mut len = 0;
 
-    fn make_file(id: FileId, base: impl AsRef<Path>) -> io::Result<StoreFile> {
-        let name = format!("{:020}.dat", id.0);
-        let mut path: PathBuf = base.as_ref().to_path_buf();
-        path.push(name);
-        StoreFile::open(id, path)
-    }
-
-    fn dump_file(file: &mut StoreFile, mut records: Vec<Record>) -> io::Result<()> {
+    fn dump_file(file: &mut StoreFile, records: Vec<Record>, order: &Comparator) -> io::Result<()> {
         if records.is_empty() {
             return Ok(());
         }
-        records.sort_by(|a, b| a.key().cmp(b.key()));
-        for record in records {
+        for record in sort_chunk(records, order) {
             file.exec(&record)?;
         }
-        file.file.flush()?;
+        file.flush()?;
         Ok(())
     }
 
     src.reset()?;
     while let Ok(record) = src.read_record() {
-        if len + record.len() > split_size_bytes {
-            let mut file = make_file(FileId(idx), &path)?;
-            dump_file(&mut file, records)?;
+        if !records.is_empty() && len + record.len() > split_size_bytes {
+            throttle.check()?;
+            let mut file =
+                StoreFile::make(FileId(idx), names.data(dir, &FileId(idx)), src.encoding)?;
+            dump_file(&mut file, records, order)?;
             result.push(file);
             records = Vec::new();
             len = 0;
@@ -372,51 +3544,169 @@ fn split(
         records.push(record);
     }
 
-    let mut file = make_file(FileId(idx), &path)?;
-    dump_file(&mut file, records)?;
+    throttle.check()?;
+    let mut file = StoreFile::make(FileId(idx), names.data(dir, &FileId(idx)), src.encoding)?;
+    dump_file(&mut file, records, order)?;
     result.push(file);
 
     for src in result.iter_mut() {
-        src.file.flush()?;
+        src.flush()?;
         src.reset()?;
     }
 
     Ok(result)
 }
 
-fn merge(dst: &mut StoreFile, srcs: &mut [StoreFile]) -> io::Result<BTreeMap<Vec<u8>, IndexEntry>> {
-    fn pick(srcs: &'_ mut [StoreFile]) -> Option<&'_ mut StoreFile> {
-        srcs.iter_mut()
-            .flat_map(|src| src.peek_record().ok().cloned().map(|rec| (rec, src)))
-            .min_by(|(a, _), (b, _)| a.key().cmp(b.key()))
-            .map(|(_, src)| src)
+/// Sorts the records of one chunk by key, keeping the order of writes among
+/// equal keys. Sorting loses the order of writes otherwise, so what each range
+/// tombstone covers is dropped first; whatever it shadows in older chunks is
+/// left to the merge.
+fn sort_chunk(records: Vec<Record>, order: &Comparator) -> Vec<Record> {
+    let mut ranges: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut kept = Vec::with_capacity(records.len());
+    for record in records.into_iter().rev() {
+        if let Record::RemoveRange(start, end) = &record {
+            ranges.push((start.clone(), end.clone()));
+        } else if ranges
+            .iter()
+            .any(|(start, end)| order.covers(start, end, record.key()))
+        {
+            continue;
+        }
+        kept.push(record);
+    }
+    kept.reverse();
+    // A stable sort by key alone: the last write of a key must stay last,
+    // which `Record`'s own order would not keep.
+    kept.sort_by(|a, b| order.compare(a.key(), b.key()));
+    kept
+}
+
+type Index = BTreeMap<Vec<u8>, RecordLocation>;
+
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// Bytes of records in `srcs`, headers left out.
+fn data_bytes(srcs: &[StoreFile]) -> io::Result<u64> {
+    let mut total = 0;
+    for src in srcs {
+        total += src.file.metadata()?.len() - src.start;
     }
+    Ok(total)
+}
 
+fn merge(
+    dst: &mut StoreFile,
+    srcs: &mut [StoreFile],
+    order: &Comparator,
+    throttle: &mut Throttle,
+) -> io::Result<(Index, usize)> {
     let mut index = BTreeMap::new();
-    let mut current_key: Option<Vec<u8>> = None;
-    let mut current_val: Option<Vec<u8>> = None;
-    while let Some(src) = pick(srcs) {
-        let record = src.read_record()?;
-        if current_key.is_none() {
-            current_key = Some(record.key().to_vec());
+    let count = merge_runs(srcs, order, |record| {
+        if let Record::Insert(key, val) = record {
+            let offset = dst.offset;
+            let entry = dst.insert(&key, &val)?;
+            index.insert(key, entry);
+            throttle.wrote(dst.offset - offset)?;
         }
-        if record.key() != current_key.as_ref().unwrap() {
-            if current_val.is_some() {
-                let key = current_key.as_ref().unwrap();
-                let val = current_val.as_ref().unwrap();
-                let entry = dst.insert(key, val)?;
-                index.insert(current_key.as_ref().unwrap().to_vec(), entry);
-            }
-            current_key = Some(record.key().to_vec());
+        Ok(())
+    })?;
+
+    dst.flush()?;
+    Ok((index, count))
+}
+
+/// Like `merge`, for a single chunk sorted by `sort_chunk` and held in
+/// memory: the last record of each key wins, and only inserts are written.
+fn merge_chunk(
+    dst: &mut StoreFile,
+    records: Vec<Record>,
+    throttle: &mut Throttle,
+) -> io::Result<(Index, usize)> {
+    let count = records.len();
+    let mut index = BTreeMap::new();
+    let mut records = records
+        .into_iter()
+        .filter(|record| record.kind() != RecordKind::RemoveRange)
+        .peekable();
+    while let Some(record) = records.next() {
+        if records.peek().map(|next| next.key() == record.key()) == Some(true) {
+            continue;
+        }
+        if let Record::Insert(key, val) = record {
+            let offset = dst.offset;
+            let entry = dst.insert(&key, &val)?;
+            index.insert(key, entry);
+            throttle.wrote(dst.offset - offset)?;
+        }
+    }
+
+    dst.flush()?;
+    Ok((index, count))
+}
+
+/// K-way merge of runs each sorted by key under `order`, oldest run first:
+/// `f` gets every key once, with its record from the newest run holding it.
+/// Only the head key of each run is kept in memory, read without its value,
+/// and a heap picks the next one.
+/// Range tombstones are always passed on, and the records they cover in
+/// older runs are skipped from their headers without being handed to `f`.
+fn merge_runs(
+    srcs: &mut [StoreFile],
+    order: &Comparator,
+    mut f: impl FnMut(Record) -> io::Result<()>,
+) -> io::Result<usize> {
+    let mut heads = BinaryHeap::with_capacity(srcs.len());
+    for (idx, src) in srcs.iter_mut().enumerate() {
+        if let Some(key) = src.peek_key()? {
+            let key = key.to_vec();
+            heads.push(Head { key, idx, order });
         }
-        current_val = record.val().map(|slice| slice.to_vec());
     }
 
-    if current_val.is_some() {
-        let entry = dst.insert(current_key.as_ref().unwrap(), current_val.as_ref().unwrap())?;
-        index.insert(current_key.as_ref().unwrap().to_vec(), entry);
+    let mut count = 0;
+    let mut current: Option<(Record, usize)> = None;
+    // Ends of the range tombstones seen so far that may still cover a key,
+    // with the run each came from.
+    let mut ranges: Vec<(Vec<u8>, usize)> = Vec::new();
+    while let Some(Head { key, idx, .. }) = heads.pop() {
+        let src = &mut srcs[idx];
+        ranges.retain(|(end, _)| order.compare(&key, end).is_lt());
+        let covered = ranges.iter().any(|(_, from)| *from > idx);
+        let record = match covered && src.skip_unless_range()? {
+            true => None,
+            false => Some(src.read_record()?),
+        };
+        count += 1;
+        if let Some(key) = src.peek_key()? {
+            let key = key.to_vec();
+            heads.push(Head { key, idx, order });
+        }
+        // Only range tombstones are read once covered.
+        let record = match record {
+            Some(record) => record,
+            None => continue,
+        };
+        if let Record::RemoveRange(_, end) = &record {
+            if let Some((prev, from)) = current.take() {
+                if prev.key() != record.key() || from >= idx {
+                    f(prev)?;
+                }
+            }
+            ranges.push((end.clone(), idx));
+            f(record)?;
+            continue;
+        }
+        if let Some((prev, _)) = current.take() {
+            if prev.key() != record.key() {
+                f(prev)?;
+            }
+        }
+        current = Some((record, idx));
     }
 
-    dst.file.flush()?;
-    Ok(index)
+    if let Some((record, _)) = current {
+        f(record)?;
+    }
+    Ok(count)
 }
@@ -0,0 +1,17 @@
+//! Log records at store lifecycle points, emitted through the `log` crate
+//! with the `logging` feature and compiled away without it.
+
+#[cfg(feature = "logging")]
+pub(crate) use log::{error, info, warn};
+
+#[cfg(not(feature = "logging"))]
+macro_rules! skip {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+pub(crate) use {skip as error, skip as info, skip as warn};
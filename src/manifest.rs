@@ -0,0 +1,162 @@
+//! `MANIFEST` keeps the layout of a store: which file is the active log and
+//! which sorted runs exist at which level, in recency order. The index can
+//! only be rebuilt correctly from the data files when this order is known.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::order::BYTEWISE;
+use crate::FileId;
+
+pub(crate) const NAME: &str = "MANIFEST";
+
+pub(crate) struct Manifest {
+    pub(crate) active: FileId,
+    pub(crate) next: u64,
+    pub(crate) levels: Vec<Vec<RunMeta>>,
+    pub(crate) timestamps: bool,
+    pub(crate) comparator: String,
+    /// When a compaction last dropped tombstones, in unix millis.
+    pub(crate) compacted_at: u64,
+    /// A constant sealed under the store's encryption key, empty if values
+    /// are not encrypted; `None` in manifests from before it was recorded.
+    pub(crate) key_check: Option<Vec<u8>>,
+}
+
+pub(crate) struct RunMeta {
+    pub(crate) id: FileId,
+    pub(crate) min: Vec<u8>,
+    pub(crate) max: Vec<u8>,
+}
+
+pub(crate) fn read(path: &Path) -> io::Result<Option<Manifest>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut src = Reader::new(&bytes);
+    let active = FileId(src.u64()?);
+    let next = src.u64()?;
+    let mut levels = Vec::new();
+    for _ in 0..src.u64()? {
+        let mut runs = Vec::new();
+        for _ in 0..src.u64()? {
+            let id = FileId(src.u64()?);
+            let min = src.bytes()?.to_vec();
+            let max = src.bytes()?.to_vec();
+            runs.push(RunMeta { id, min, max });
+        }
+        levels.push(runs);
+    }
+    // Manifests written before timestamps existed end here.
+    let timestamps = !src.is_empty() && src.u64()? != 0;
+    // And these before comparators did.
+    let comparator = match src.is_empty() {
+        true => BYTEWISE.to_string(),
+        false => String::from_utf8_lossy(src.bytes()?).to_string(),
+    };
+    // And these before compactions were marked.
+    let compacted_at = match src.is_empty() {
+        true => 0,
+        false => src.u64()?,
+    };
+    // And these before the encryption key was checked.
+    let key_check = match src.is_empty() {
+        true => None,
+        false => Some(src.bytes()?.to_vec()),
+    };
+    Ok(Some(Manifest {
+        active,
+        next,
+        levels,
+        timestamps,
+        comparator,
+        compacted_at,
+        key_check,
+    }))
+}
+
+pub(crate) fn write(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut dst = Vec::new();
+    put_u64(&mut dst, manifest.active.0);
+    put_u64(&mut dst, manifest.next);
+    put_u64(&mut dst, manifest.levels.len() as u64);
+    for runs in manifest.levels.iter() {
+        put_u64(&mut dst, runs.len() as u64);
+        for run in runs {
+            put_u64(&mut dst, run.id.0);
+            put_bytes(&mut dst, &run.min);
+            put_bytes(&mut dst, &run.max);
+        }
+    }
+    put_u64(&mut dst, manifest.timestamps as u64);
+    put_bytes(&mut dst, manifest.comparator.as_bytes());
+    put_u64(&mut dst, manifest.compacted_at);
+    put_bytes(&mut dst, manifest.key_check.as_deref().unwrap_or_default());
+    write_atomic(path, &dst)
+}
+
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    // Appended rather than set as the extension: prefixes that differ only
+    // after a dot would share a temp file otherwise.
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    sync_dir(path)
+}
+
+/// Makes a rename or creation in `path`'s directory durable.
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn put_u64(dst: &mut Vec<u8>, val: u64) {
+    dst.extend_from_slice(&val.to_be_bytes());
+}
+
+pub(crate) fn put_bytes(dst: &mut Vec<u8>, val: &[u8]) {
+    put_u64(dst, val.len() as u64);
+    dst.extend_from_slice(val);
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
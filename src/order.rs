@@ -0,0 +1,217 @@
+//! The order keys are sorted in: by bytes unless `StoreOptions::comparator`
+//! sets another. Sorted files are only valid under the order they were
+//! written in, so a store records its comparator's name in the manifest.
+
+use std::cmp::Ordering;
+use std::collections::{btree_set, BTreeSet};
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+pub type CompareFn = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+
+pub(crate) const BYTEWISE: &str = "bytewise";
+
+/// A named total order on keys. It must only find equal keys that are equal
+/// byte for byte: lookups still go by the bytes.
+#[derive(Clone)]
+pub struct Comparator {
+    name: String,
+    /// `None` for the plain byte order, which the index is sorted by already.
+    cmp: Option<Arc<CompareFn>>,
+}
+
+impl Comparator {
+    pub fn new(name: &str, cmp: Box<CompareFn>) -> Self {
+        Self {
+            name: name.to_string(),
+            cmp: Some(Arc::from(cmp)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match &self.cmp {
+            Some(cmp) => cmp(a, b),
+            None => a.cmp(b),
+        }
+    }
+
+    pub(crate) fn is_bytewise(&self) -> bool {
+        self.cmp.is_none()
+    }
+
+    pub(crate) fn contains(&self, start: Bound<&[u8]>, end: Bound<&[u8]>, key: &[u8]) -> bool {
+        let above = match start {
+            Bound::Included(start) => self.compare(start, key).is_le(),
+            Bound::Excluded(start) => self.compare(start, key).is_lt(),
+            Bound::Unbounded => true,
+        };
+        let below = match end {
+            Bound::Included(end) => self.compare(key, end).is_le(),
+            Bound::Excluded(end) => self.compare(key, end).is_lt(),
+            Bound::Unbounded => true,
+        };
+        above && below
+    }
+
+    /// Whether no key can lie between `start` and `end`, as when they are
+    /// reversed.
+    pub(crate) fn is_empty(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+        match (start, end) {
+            (Bound::Included(start), Bound::Included(end)) => self.compare(start, end).is_gt(),
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) => self.compare(start, end).is_ge(),
+            _ => false,
+        }
+    }
+
+    /// Whether `key` is in the range tombstone `[start, end)`.
+    pub(crate) fn covers(&self, start: &[u8], end: &[u8], key: &[u8]) -> bool {
+        self.contains(Bound::Included(start), Bound::Excluded(end), key)
+    }
+}
+
+impl Default for Comparator {
+    fn default() -> Self {
+        Self {
+            name: BYTEWISE.to_string(),
+            cmp: None,
+        }
+    }
+}
+
+impl fmt::Debug for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Comparator").field(&self.name).finish()
+    }
+}
+
+/// The keys of the index in the order of a comparator other than the byte
+/// order, kept up to date along with the index so that scans need not sort
+/// it. Costs a second copy of every key; empty under the byte order, which
+/// the index is sorted by already.
+pub(crate) struct OrderedKeys {
+    cmp: Option<Arc<CompareFn>>,
+    keys: BTreeSet<OrderedKey>,
+}
+
+impl OrderedKeys {
+    pub(crate) fn new(order: &Comparator) -> Self {
+        Self {
+            cmp: order.cmp.clone(),
+            keys: BTreeSet::new(),
+        }
+    }
+
+    fn key(&self, key: &[u8]) -> Option<OrderedKey> {
+        self.cmp.as_ref().map(|cmp| OrderedKey {
+            key: key.to_vec(),
+            cmp: cmp.clone(),
+        })
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8]) {
+        if let Some(key) = self.key(key) {
+            self.keys.insert(key);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &[u8]) {
+        if let Some(key) = self.key(key) {
+            self.keys.remove(&key);
+        }
+    }
+
+    /// Replaces the keys with `keys`, for an index loaded or rebuilt whole.
+    pub(crate) fn reset<'a>(&mut self, keys: impl Iterator<Item = &'a Vec<u8>>) {
+        self.keys.clear();
+        for key in keys {
+            self.insert(key);
+        }
+    }
+
+    /// Keys between `start` and `end`, which must not be reversed.
+    pub(crate) fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> OrderedRange<'_> {
+        let bound = |bound: Bound<&[u8]>| match bound {
+            Bound::Included(key) => Bound::Included(self.key(key).unwrap()),
+            Bound::Excluded(key) => Bound::Excluded(self.key(key).unwrap()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        OrderedRange(self.keys.range((bound(start), bound(end))))
+    }
+}
+
+pub(crate) struct OrderedRange<'a>(btree_set::Range<'a, OrderedKey>);
+
+impl<'a> Iterator for OrderedRange<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|key| key.key.as_slice())
+    }
+}
+
+impl DoubleEndedIterator for OrderedRange<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|key| key.key.as_slice())
+    }
+}
+
+struct OrderedKey {
+    key: Vec<u8>,
+    cmp: Arc<CompareFn>,
+}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.cmp)(&self.key, &other.key)
+    }
+}
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for OrderedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for OrderedKey {}
+
+/// The head of one input of a k-way merge. Ordered by key under `order`,
+/// then by input, and reversed so that `BinaryHeap` pops the least first.
+pub(crate) struct Head<'a> {
+    pub(crate) key: Vec<u8>,
+    pub(crate) idx: usize,
+    pub(crate) order: &'a Comparator,
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.order.compare(&self.key, &other.key);
+        ord.then(self.idx.cmp(&other.idx)).reverse()
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head<'_> {}
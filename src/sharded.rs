@@ -0,0 +1,229 @@
+//! `ShardedStore` spreads keys over several stores, one per subdirectory,
+//! by a hash of the key. The shard count and hash are recorded in `SHARDS`
+//! so that a reopened store routes every key to the shard holding it.
+
+use std::collections::BinaryHeap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{put_u64, write_atomic, Reader};
+use crate::order::Head;
+use crate::util::crc32;
+use crate::{kv, Comparator, Entry, Iter, Store, StoreOptions};
+
+const NAME: &str = "SHARDS";
+
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ShardHash {
+    #[default]
+    Crc32,
+    Fnv1a,
+    /// A hash of the caller's. `id` is what `SHARDS` records to tell it
+    /// apart, so it must change whenever `hash` does.
+    Custom {
+        id: u32,
+        hash: fn(&[u8]) -> u64,
+    },
+}
+
+impl ShardHash {
+    pub fn hash(self, key: &[u8]) -> u64 {
+        match self {
+            ShardHash::Crc32 => crc32(key) as u64,
+            ShardHash::Fnv1a => key.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+            }),
+            ShardHash::Custom { hash, .. } => hash(key),
+        }
+    }
+
+    fn code(self) -> u64 {
+        match self {
+            ShardHash::Crc32 => 1,
+            ShardHash::Fnv1a => 2,
+            ShardHash::Custom { id, .. } => 1 << 32 | id as u64,
+        }
+    }
+}
+
+/// Hashes are the same if `SHARDS` records them the same.
+impl PartialEq for ShardHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for ShardHash {}
+
+pub struct ShardedStore {
+    base: PathBuf,
+    hash: ShardHash,
+    shards: Vec<Store>,
+}
+
+impl ShardedStore {
+    pub fn open(base: &str, shards: usize, hash: ShardHash) -> kv::Result<Self> {
+        Self::open_with(base, shards, hash, StoreOptions::default())
+    }
+
+    /// Opens or creates `shards` stores under `base`, each with `options`.
+    /// Fails if `base` was created with a different shard count or hash.
+    pub fn open_with(
+        base: &str,
+        shards: usize,
+        hash: ShardHash,
+        options: StoreOptions,
+    ) -> kv::Result<Self> {
+        if shards == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        let base = PathBuf::from(base);
+        std::fs::create_dir_all(&base)?;
+        match read_layout(&base)? {
+            Some(found) if found != (shards, hash.code()) => {
+                return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+            }
+            Some(_) => (),
+            None => {
+                let mut dst = Vec::new();
+                put_u64(&mut dst, shards as u64);
+                put_u64(&mut dst, hash.code());
+                write_atomic(&base.join(NAME), &dst)?;
+            }
+        }
+
+        let mut stores = Vec::with_capacity(shards);
+        for shard in 0..shards {
+            let dir = base.join(format!("shard-{:04}", shard));
+            let dir = dir.to_string_lossy();
+            stores.push(Store::open_or_create_with(&dir, options.clone())?);
+        }
+        Ok(Self {
+            base,
+            hash,
+            shards: stores,
+        })
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base
+    }
+
+    /// The shard `key` lives in.
+    pub fn shard_of(&self, key: &[u8]) -> usize {
+        (self.hash.hash(key) % self.shards.len() as u64) as usize
+    }
+
+    pub fn shards(&self) -> &[Store] {
+        &self.shards
+    }
+
+    pub fn get(&self, key: &[u8]) -> kv::Result<Option<Vec<u8>>> {
+        self.shards[self.shard_of(key)].get(key)
+    }
+
+    pub fn insert(&mut self, key: &[u8], val: &[u8]) -> kv::Result<()> {
+        let shard = self.shard_of(key);
+        self.shards[shard].insert(key, val)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> kv::Result<bool> {
+        let shard = self.shard_of(key);
+        self.shards[shard].remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|store| store.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn flush(&mut self) -> kv::Result<()> {
+        for store in self.shards.iter_mut() {
+            store.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Compacts every shard, on rayon's thread pool with the `rayon` feature.
+    pub fn compact(&mut self) -> kv::Result<()> {
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            self.shards
+                .par_iter_mut()
+                .try_for_each(|store| store.compact())
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            self.shards.iter_mut().try_for_each(|store| store.compact())
+        }
+    }
+
+    /// Live entries of all shards merged into key order.
+    pub fn iter(&self) -> ShardedIter<'_> {
+        ShardedIter {
+            iters: self.shards.iter().map(|store| store.iter()).collect(),
+            order: &self.shards[0].options.comparator,
+            heads: BinaryHeap::with_capacity(self.shards.len()),
+            vals: vec![Vec::new(); self.shards.len()],
+            pending: (0..self.shards.len()).collect(),
+        }
+    }
+}
+
+pub struct ShardedIter<'a> {
+    iters: Vec<Iter<'a>>,
+    order: &'a Comparator,
+    heads: BinaryHeap<Head<'a>>,
+    /// The value of each shard's entry in `heads`.
+    vals: Vec<Vec<u8>>,
+    /// Shards whose next entry is yet to be pulled into `heads`.
+    pending: Vec<usize>,
+}
+
+impl ShardedIter<'_> {
+    fn pull(&mut self, shard: usize) -> kv::Result<()> {
+        if let Some(entry) = self.iters[shard].next() {
+            let (key, val) = entry?;
+            let order = self.order;
+            self.heads.push(Head {
+                key,
+                idx: shard,
+                order,
+            });
+            self.vals[shard] = val;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ShardedIter<'_> {
+    type Item = kv::Result<Entry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(shard) = self.pending.pop() {
+            if let Err(e) = self.pull(shard) {
+                return Some(Err(e));
+            }
+        }
+        let Head {
+            key, idx: shard, ..
+        } = self.heads.pop()?;
+        self.pending.push(shard);
+        Some(Ok((key, std::mem::take(&mut self.vals[shard]))))
+    }
+}
+
+/// The shard count and hash code recorded in `base`.
+fn read_layout(base: &Path) -> io::Result<Option<(usize, u64)>> {
+    let bytes = match std::fs::read(base.join(NAME)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut src = Reader::new(&bytes);
+    let shards = src.u64()? as usize;
+    Ok(Some((shards, src.u64()?)))
+}
@@ -0,0 +1,75 @@
+//! Counters, histograms and gauges reported through the `metrics` facade
+//! with the `metrics` feature; every call here is an empty inline function
+//! without it.
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+pub(crate) const INSERTS: &str = "yalskv_inserts";
+pub(crate) const REMOVES: &str = "yalskv_removes";
+pub(crate) const LOOKUPS: &str = "yalskv_lookups";
+pub(crate) const VALUE_BYTES: &str = "yalskv_value_bytes";
+pub(crate) const LOOKUP_SECONDS: &str = "yalskv_lookup_seconds";
+pub(crate) const INDEX_KEYS: &str = "yalskv_index_keys";
+pub(crate) const FILES: &str = "yalskv_files";
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn count(name: &'static str) {
+    metrics::counter!(name).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn record(name: &'static str, value: f64) {
+    metrics::histogram!(name).record(value);
+}
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn set(name: &'static str, value: f64) {
+    metrics::gauge!(name).set(value);
+}
+
+/// Measures from `start` to `stop` into a histogram of seconds.
+#[cfg(feature = "metrics")]
+pub(crate) struct Timer(Instant);
+
+#[cfg(feature = "metrics")]
+impl Timer {
+    #[inline]
+    pub(crate) fn start() -> Self {
+        Self(Instant::now())
+    }
+
+    #[inline]
+    pub(crate) fn stop(self, name: &'static str) {
+        record(name, self.0.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn count(_: &'static str) {}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record(_: &'static str, _: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn set(_: &'static str, _: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Timer;
+
+#[cfg(not(feature = "metrics"))]
+impl Timer {
+    #[inline(always)]
+    pub(crate) fn start() -> Self {
+        Self
+    }
+
+    #[inline(always)]
+    pub(crate) fn stop(self, _: &'static str) {}
+}
@@ -0,0 +1,74 @@
+//! `U64KeyStore` keys a store by `u64`, written big-endian so that the byte
+//! order of keys is their numeric order and a range of ids is a range of
+//! keys. This holds under the default comparator only.
+
+use std::ops::{Bound, Range};
+
+use crate::{kv, util, Iter, Store};
+
+pub struct U64KeyStore {
+    store: Store,
+}
+
+impl U64KeyStore {
+    /// Wraps `store`, which must hold no keys but those written through
+    /// the wrapper.
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> Store {
+        self.store
+    }
+
+    pub fn get(&self, key: u64) -> kv::Result<Option<Vec<u8>>> {
+        self.store.get(&key.to_be_bytes())
+    }
+
+    pub fn insert(&mut self, key: u64, val: &[u8]) -> kv::Result<()> {
+        self.store.insert(&key.to_be_bytes(), val)
+    }
+
+    pub fn remove(&mut self, key: u64) -> kv::Result<bool> {
+        self.store.remove(&key.to_be_bytes())
+    }
+
+    /// Entries with keys in `range`, in numeric order.
+    pub fn range(&self, range: Range<u64>) -> U64Iter<'_> {
+        let (start, end) = (range.start.to_be_bytes(), range.end.to_be_bytes());
+        U64Iter {
+            iter: self
+                .store
+                .range(Bound::Included(&start), Bound::Excluded(&end)),
+        }
+    }
+
+    pub fn flush(&mut self) -> kv::Result<()> {
+        self.store.flush()
+    }
+}
+
+pub struct U64Iter<'a> {
+    iter: Iter<'a>,
+}
+
+impl Iterator for U64Iter<'_> {
+    type Item = kv::Result<(u64, Vec<u8>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, val) = match self.iter.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match <[u8; 8]>::try_from(key.as_slice()) {
+            Ok(id) => Ok((u64::from_be_bytes(id), val)),
+            Err(_) => Err(kv::Error::Unknown(format!(
+                "key {} is not a u64",
+                util::hex(&key)
+            ))),
+        })
+    }
+}
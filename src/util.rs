@@ -1,32 +1,150 @@
+use std::fmt;
+
 use rand::prelude::SliceRandom;
 use rand::prelude::StdRng;
-use rand::{RngCore, SeedableRng};
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::Store;
 
 pub fn data(count: usize, seed: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+    data_iter(count, seed).collect()
+}
+
+pub fn data_iter(count: usize, seed: u64) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
     let mut rng = StdRng::seed_from_u64(seed);
-    (0..count)
-        .into_iter()
-        .map(|_| {
-            let mut key = Vec::with_capacity(64);
-            let mut val = Vec::with_capacity(64);
-            for _ in 0..8 {
-                key.extend_from_slice(&rng.next_u64().to_be_bytes());
-                val.extend_from_slice(&rng.next_u64().to_be_bytes());
-            }
-            (key, val)
-        })
-        .collect()
-}
-
-pub fn mix<T>(mut data: Vec<T>, seed: u64) -> Vec<T> {
-    let mut rng = StdRng::seed_from_u64(seed);
-    data.shuffle(&mut rng);
+    (0..count).map(move |_| {
+        let mut key = Vec::with_capacity(64);
+        let mut val = Vec::with_capacity(64);
+        for _ in 0..8 {
+            key.extend_from_slice(&rng.next_u64().to_be_bytes());
+            val.extend_from_slice(&rng.next_u64().to_be_bytes());
+        }
+        (key, val)
+    })
+}
+
+pub fn mix<T>(data: Vec<T>, seed: u64) -> Vec<T> {
+    mix_with(data, &mut StdRng::seed_from_u64(seed))
+}
+
+/// Shuffles `data` with `rng`: vectors of the same length mixed with clones
+/// of one rng come out in the same order.
+pub fn mix_with<T, R: Rng + ?Sized>(mut data: Vec<T>, rng: &mut R) -> Vec<T> {
+    data.shuffle(rng);
     data
 }
 
 pub fn hex(src: &[u8]) -> String {
-    src.iter()
-        .map(|x| format!("{:02x}", x))
-        .collect::<Vec<_>>()
-        .concat()
+    let mut dst = String::with_capacity(src.len() * 2);
+    write_hex(&mut dst, src).unwrap();
+    dst
+}
+
+pub fn hex_upper(src: &[u8]) -> String {
+    let mut dst = String::with_capacity(src.len() * 2);
+    write_hex_upper(&mut dst, src).unwrap();
+    dst
+}
+
+/// Writes `src` as lowercase hex to `dst` without allocating.
+pub fn write_hex<W: fmt::Write>(dst: &mut W, src: &[u8]) -> fmt::Result {
+    for byte in src {
+        write!(dst, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// Like `write_hex`, in uppercase.
+pub fn write_hex_upper<W: fmt::Write>(dst: &mut W, src: &[u8]) -> fmt::Result {
+    for byte in src {
+        write!(dst, "{:02X}", byte)?;
+    }
+    Ok(())
+}
+
+/// Counts of live key and value lengths in power-of-two buckets: bucket `0`
+/// holds zero lengths and bucket `i > 0` holds lengths in `2^(i-1)..2^i`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SizeHistogram {
+    pub keys: Vec<usize>,
+    pub vals: Vec<usize>,
+}
+
+impl SizeHistogram {
+    pub fn bucket(len: usize) -> usize {
+        (usize::BITS - len.leading_zeros()) as usize
+    }
+
+    fn add(counts: &mut Vec<usize>, len: usize) {
+        let bucket = Self::bucket(len);
+        if counts.len() <= bucket {
+            counts.resize(bucket + 1, 0);
+        }
+        counts[bucket] += 1;
+    }
+}
+
+/// Buckets values by the length `get` returns, leaving out the timestamp
+/// or encryption overhead stored with them.
+pub fn size_histogram(store: &Store) -> SizeHistogram {
+    let mut histogram = SizeHistogram::default();
+    for (key, entry) in store.index.iter() {
+        SizeHistogram::add(&mut histogram.keys, key.len());
+        SizeHistogram::add(&mut histogram.vals, store.value_len(entry) as usize);
+    }
+    histogram
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Streaming CRC-32 (IEEE 802.3, as used by zlib and PNG).
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = CRC32_TABLE[((self.0 ^ *byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
 }
@@ -0,0 +1,237 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{dir, key, val};
+use yalskv::format::{self, ByteOrder, Encoding};
+use yalskv::{kv, Store, StoreOptions};
+
+fn now_millis() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_millis() as u64
+}
+
+/// A clock reading strictly after every write so far, and strictly before
+/// every write to come.
+fn mark() -> u64 {
+    std::thread::sleep(Duration::from_millis(2));
+    let mark = now_millis();
+    std::thread::sleep(Duration::from_millis(2));
+    mark
+}
+
+fn stamped() -> StoreOptions {
+    StoreOptions::new().timestamps(true)
+}
+
+#[test]
+fn modified_since_returns_only_later_writes() {
+    let dir = dir("modified_since");
+    let mut store = Store::open_with(&dir, stamped()).unwrap();
+    for i in 0..10 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let since = mark();
+    for i in 5..15 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    let later: Vec<_> = (5..15).map(key).collect();
+    assert_eq!(store.modified_since(since).unwrap(), later);
+    assert_eq!(store.modified_since(0).unwrap().len(), 15);
+
+    let (value, timestamp) = store.lookup_with_meta(&key(7)).unwrap().unwrap();
+    assert_eq!(value, val(1, 7));
+    assert!(timestamp >= since && timestamp <= now_millis());
+    let (_, early) = store.lookup_with_meta(&key(0)).unwrap().unwrap();
+    assert!(early < since);
+    assert_eq!(store.lookup_with_meta(b"absent").unwrap(), None);
+
+    store.reduce(1 << 20).unwrap();
+    drop(store);
+    let store = Store::open_with(&dir, stamped()).unwrap();
+    assert_eq!(store.modified_since(since).unwrap(), later);
+    assert_eq!(
+        store.lookup_with_meta(&key(7)).unwrap().unwrap().1,
+        timestamp
+    );
+    assert_eq!(store.get(&key(0)).unwrap(), Some(val(0, 0)));
+}
+
+#[test]
+fn timestamps_need_the_option() {
+    let mut store = Store::open(&dir("no_timestamps")).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    assert!(matches!(store.modified_since(0), Err(kv::Error::IO(_))));
+    assert!(matches!(
+        store.lookup_with_meta(b"a"),
+        Err(kv::Error::IO(_))
+    ));
+}
+
+fn contents(store: &Store) -> Vec<(Vec<u8>, Vec<u8>)> {
+    store.iter().map(Result::unwrap).collect()
+}
+
+#[test]
+fn full_and_incremental_backups_restore_the_store() {
+    let dir = dir("backup");
+    let backups = common::dir("backup_files");
+    let (full, incremental) = (format!("{backups}/full"), format!("{backups}/incremental"));
+    let mut store = Store::open_with(&dir, stamped()).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.remove(&key(0)).unwrap();
+    // The value last written before a backup is in the next one as well,
+    // along with anything else written in the same millisecond.
+    std::thread::sleep(Duration::from_millis(2));
+    store.insert(b"last", b"").unwrap();
+    let since = store.backup_incremental(0, &full).unwrap();
+    assert_eq!(
+        Some(since),
+        store.lookup_with_meta(b"last").unwrap().map(|(_, t)| t)
+    );
+
+    std::thread::sleep(Duration::from_millis(2));
+    for i in 50..150 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.remove(&key(1)).unwrap();
+    store.remove_range(&key(20), &key(30)).unwrap();
+    let next = store.backup_incremental(since, &incremental).unwrap();
+    assert!(next >= since);
+
+    let mut restored = Store::open(&common::dir("backup_restored")).unwrap();
+    // 99 values, `last` and the tombstone of the first key.
+    assert_eq!(restored.apply_incremental(&full).unwrap(), 101);
+    restored.insert(&key(25), b"stale").unwrap();
+    // Two tombstones, the range, the 100 new values and the last one again.
+    assert_eq!(restored.apply_incremental(&incremental).unwrap(), 104);
+    assert_eq!(contents(&restored), contents(&store));
+    assert_eq!(restored.get(&key(25)).unwrap(), None);
+}
+
+#[test]
+fn incremental_backups_fail_once_tombstones_are_compacted_away() {
+    let dir = dir("backup_compacted");
+    let backups = common::dir("backup_compacted_files");
+    let mut store = Store::open_with(&dir, stamped()).unwrap();
+    for i in 0..10 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let since = store
+        .backup_incremental(0, &format!("{backups}/full"))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+    store.remove(&key(3)).unwrap();
+    store.reduce(1 << 20).unwrap();
+    drop(store);
+
+    let store = Store::open_with(&dir, stamped()).unwrap();
+    let incremental = format!("{backups}/incremental");
+    assert!(matches!(
+        store.backup_incremental(since, &incremental),
+        Err(kv::Error::Unknown(_))
+    ));
+    store.backup_incremental(0, &incremental).unwrap();
+}
+
+/// Records in the data file format, built by hand.
+struct Stream {
+    encoding: Encoding,
+    bytes: Vec<u8>,
+}
+
+impl Stream {
+    fn new(encoding: Encoding, ranges: Option<bool>) -> Self {
+        let bytes = match ranges {
+            Some(ranges) => format::file_header(encoding, ranges).to_vec(),
+            None => Vec::new(),
+        };
+        Self { encoding, bytes }
+    }
+
+    fn put(&mut self, fields: &[u64], data: &[&[u8]]) -> &mut Self {
+        for field in fields {
+            match self.encoding {
+                Encoding::Fixed(order) => self.bytes.extend_from_slice(&order.encode(*field)),
+                Encoding::Varint => self.encoding.put(&mut self.bytes, *field),
+            }
+        }
+        for data in data {
+            self.bytes.extend_from_slice(data);
+        }
+        self
+    }
+
+    fn insert(&mut self, key: &[u8], val: &[u8]) -> &mut Self {
+        let lens = [format::INSERT, key.len() as u64, val.len() as u64];
+        self.put(&lens, &[key, val])
+    }
+
+    fn remove(&mut self, key: &[u8]) -> &mut Self {
+        self.put(&[format::REMOVE, key.len() as u64], &[key])
+    }
+
+    fn remove_range(&mut self, start: &[u8], end: &[u8]) -> &mut Self {
+        let lens = [format::REMOVE_RANGE, start.len() as u64, end.len() as u64];
+        self.put(&lens, &[start, end])
+    }
+}
+
+#[test]
+fn apply_log_applies_a_hand_built_stream() {
+    let mut store = Store::open(&dir("apply_log")).unwrap();
+    store.insert(b"gone", b"0").unwrap();
+    let mut stream = Stream::new(Encoding::default(), None);
+    stream
+        .insert(b"a", b"1")
+        .insert(b"b", b"2")
+        .insert(b"a", b"3")
+        .remove(b"b")
+        .remove(b"gone")
+        .insert(b"", b"");
+    assert_eq!(store.apply_log(&stream.bytes[..]).unwrap(), 6);
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(
+        pairs,
+        vec![(b"".to_vec(), b"".to_vec()), (b"a".to_vec(), b"3".to_vec())]
+    );
+    assert_eq!(store.apply_log(&[][..]).unwrap(), 0);
+
+    for encoding in [Encoding::Fixed(ByteOrder::Little), Encoding::Varint] {
+        let mut store = Store::open(&common::dir("apply_log_header")).unwrap();
+        let mut stream = Stream::new(encoding, Some(true));
+        stream
+            .insert(b"a", b"1")
+            .insert(b"c", b"2")
+            .insert(b"e", b"3")
+            .remove_range(b"b", b"d");
+        assert_eq!(store.apply_log(&stream.bytes[..]).unwrap(), 4);
+        let keys: Vec<&[u8]> = store.keys().collect();
+        assert_eq!(keys, vec![&b"a"[..], &b"e"[..]], "{encoding:?}");
+    }
+}
+
+#[test]
+fn apply_log_stops_at_the_first_bad_record() {
+    let mut store = Store::open(&dir("apply_log_bad")).unwrap();
+    let mut stream = Stream::new(Encoding::default(), Some(false));
+    stream.insert(b"a", b"1").insert(b"b", b"2");
+    let offset = stream.bytes.len();
+    stream.put(&[9, 1], &[b"x"]);
+    match store.apply_log(&stream.bytes[..]) {
+        Err(kv::Error::IO(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), format!("bad record at offset {offset}"));
+        }
+        other => panic!("applied a bad record: {other:?}"),
+    }
+    assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+    let mut stream = Stream::new(Encoding::default(), None);
+    stream.insert(b"c", b"3");
+    let torn = &stream.bytes[..stream.bytes.len() - 1];
+    assert!(store.apply_log(torn).is_err());
+    assert_eq!(store.get(b"c").unwrap(), None);
+}
@@ -0,0 +1,37 @@
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+/// A fresh, empty directory named `name` under cargo's scratch directory
+/// for integration tests.
+pub fn dir(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+pub fn key(i: u32) -> Vec<u8> {
+    format!("k{i:05}").into_bytes()
+}
+
+pub fn val(round: u32, i: u32) -> Vec<u8> {
+    format!("v{round}-{i}").into_bytes()
+}
+
+/// Ids of the data files currently in the store, in id order.
+pub fn file_ids(store: &mut yalskv::Store) -> Vec<u64> {
+    let stats = store.file_stats().unwrap();
+    stats.iter().map(|stat| stat.file_id.get()).collect()
+}
+
+/// Copies the files of `src` into `dst`, as a crash would leave them on
+/// disk while the store in `src` is still open.
+pub fn copy_dir(src: &str, dst: &str) {
+    for entry in std::fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_file() {
+            std::fs::copy(entry.path(), PathBuf::from(dst).join(entry.file_name())).unwrap();
+        }
+    }
+}
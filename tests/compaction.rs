@@ -0,0 +1,815 @@
+mod common;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use common::{dir, file_ids, key, val};
+use yalskv::{format, util, CompactionStrategy, Store, StoreOptions};
+
+fn leveled() -> StoreOptions {
+    StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(4 * 1024)
+        .level0_runs(1)
+}
+
+#[test]
+fn leveled_compaction_only_rewrites_overlapping_runs() {
+    let dir = dir("leveled_overlapping_runs");
+    let mut store = Store::open_with(&dir, leveled().level_fanout(100)).unwrap();
+    for i in 0..2000 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    let before = file_ids(&mut store);
+    assert!(before.len() > 4, "{before:?}");
+
+    for i in 10..20 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.compact().unwrap();
+    let after = file_ids(&mut store);
+    let kept = before.iter().filter(|id| after.contains(id)).count();
+    // The active log and the one run holding keys 10..20 are replaced.
+    assert_eq!(kept, before.len() - 2, "{before:?} -> {after:?}");
+
+    for i in 0..2000 {
+        let round = if (10..20).contains(&i) { 1 } else { 0 };
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(round, i)));
+    }
+}
+
+#[test]
+fn leveled_compaction_matches_a_model_and_survives_reopen() {
+    let dir = dir("leveled_model");
+    let options = || leveled().run_bytes(2000).level0_runs(2).level_fanout(2);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    let mut model = BTreeMap::new();
+    for round in 0..6 {
+        for i in 0..200 {
+            let k = key((i * 7 + round * 13) % 500);
+            if i % 5 == 0 {
+                store.remove(&k).unwrap();
+                model.remove(&k);
+            } else {
+                store.insert(&k, &val(round, i)).unwrap();
+                model.insert(k, val(round, i));
+            }
+        }
+        store.compact().unwrap();
+        assert_eq!(store.len(), model.len());
+        for (k, v) in model.iter() {
+            assert_eq!(store.get(k).unwrap().as_ref(), Some(v));
+        }
+    }
+    drop(store);
+
+    let store = Store::open_with(&dir, options()).unwrap();
+    let pairs: BTreeMap<_, _> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(pairs, model);
+}
+
+#[test]
+fn leveled_options_that_cannot_converge_are_rejected() {
+    let dir = dir("leveled_zero_options");
+    let invalid = [
+        leveled().run_bytes(0),
+        leveled().level0_runs(0),
+        leveled().level_fanout(0),
+        leveled().level_fanout(1),
+    ];
+    for options in invalid {
+        match Store::open_with(&dir, options) {
+            Err(yalskv::kv::Error::IO(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput)
+            }
+            Err(e) => panic!("unexpected error: {e:?}"),
+            Ok(_) => panic!("opened with an invalid option"),
+        }
+    }
+}
+
+#[test]
+fn leveled_compaction_of_an_empty_store_does_nothing() {
+    let dir = dir("leveled_empty");
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    store.compact().unwrap();
+    store.compact().unwrap();
+    assert_eq!(store.len(), 0);
+    assert_eq!(file_ids(&mut store).len(), 1);
+}
+
+#[test]
+fn level_limits_saturate_instead_of_overflowing() {
+    let dir = dir("leveled_huge_fanout");
+    let mut store = Store::open_with(&dir, leveled().level_fanout(u64::MAX)).unwrap();
+    for round in 0..4 {
+        for i in 0..500 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.compact().unwrap();
+    }
+    for i in 0..500 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(3, i)));
+    }
+}
+
+#[test]
+fn size_tiered_compaction_waits_for_enough_similar_runs() {
+    let dir = dir("size_tiered_threshold");
+    let options = StoreOptions::new()
+        .compaction(CompactionStrategy::SizeTiered)
+        .tier_min_runs(3);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let mut files = Vec::new();
+    for round in 0..3 {
+        for i in 0..100 {
+            store.insert(&key(round * 100 + i), &val(round, i)).unwrap();
+        }
+        store.compact().unwrap();
+        files.push(file_ids(&mut store).len());
+    }
+    // One run per flush, plus the active log, until the third run of the
+    // same size is there to merge the tier into one.
+    assert_eq!(files, vec![2, 3, 2]);
+    assert_eq!(store.len(), 300);
+    for round in 0..3 {
+        for i in 0..100 {
+            assert_eq!(
+                store.get(&key(round * 100 + i)).unwrap(),
+                Some(val(round, i))
+            );
+        }
+    }
+}
+
+#[test]
+fn full_compaction_merges_everything_into_one_file() {
+    let dir = dir("full_compaction");
+    let mut store = Store::open_with(&dir, StoreOptions::new()).unwrap();
+    for round in 0..3 {
+        for i in 0..100 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.compact().unwrap();
+        assert_eq!(file_ids(&mut store).len(), 1);
+    }
+    assert_eq!(store.disk_record_count().unwrap(), 100);
+}
+
+/// Every record on disk, as its key and whether it holds a value.
+fn raw_records(store: &mut Store) -> Vec<(Vec<u8>, bool)> {
+    let records = store.iter_raw().map(Result::unwrap);
+    let records = records.map(|(_, _, record)| (record.key().to_vec(), record.is_insert()));
+    records.collect()
+}
+
+fn count(records: &[(Vec<u8>, bool)], key: &[u8]) -> usize {
+    records.iter().filter(|(k, _)| k == key).count()
+}
+
+#[test]
+fn compact_range_only_rewrites_records_in_the_range() {
+    let dir = dir("compact_range");
+    let mut store = Store::open_with(&dir, StoreOptions::new()).unwrap();
+    for round in 0..3 {
+        for k in [b"a", b"b", b"c", b"d", b"e"] {
+            store.insert(k, &val(round, 0)).unwrap();
+        }
+    }
+    store.remove(b"c").unwrap();
+    let dead = store.dead_bytes();
+
+    let range = (Bound::Included(&b"b"[..]), Bound::Excluded(&b"d"[..]));
+    store.compact_range(range.0, range.1).unwrap();
+    let records = raw_records(&mut store);
+    assert_eq!(count(&records, b"a"), 3);
+    assert_eq!(count(&records, b"b"), 1);
+    assert_eq!(count(&records, b"c"), 0);
+    assert_eq!(count(&records, b"d"), 3);
+    assert_eq!(count(&records, b"e"), 3);
+    assert!(store.dead_bytes() < dead);
+
+    for k in [b"a", b"b", b"d", b"e"] {
+        assert_eq!(store.get(k).unwrap(), Some(val(2, 0)));
+    }
+    assert_eq!(store.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn compact_range_keeps_removals_across_runs_after_a_replay() {
+    let dir = dir("compact_range_runs");
+    let options = || leveled().level0_runs(4);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    for i in 0..50 {
+        store.remove(&key(i)).unwrap();
+    }
+    store.compact().unwrap();
+
+    let (start, end) = (key(0), key(50));
+    let range = (Bound::Included(&start[..]), Bound::Excluded(&end[..]));
+    store.compact_range(range.0, range.1).unwrap();
+    let records = raw_records(&mut store);
+    assert!((0..50).all(|i| count(&records, &key(i)) == 0));
+    assert_eq!(store.len(), 50);
+    drop(store);
+
+    // Without the checkpoint the index comes from the files alone.
+    std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+    let store = Store::open_with(&dir, options()).unwrap();
+    assert_eq!(store.len(), 50);
+    for i in 0..100 {
+        let expected = (i >= 50).then(|| val(0, i));
+        assert_eq!(store.get(&key(i)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn dead_bytes_count_overwritten_and_removed_records() {
+    let dir = dir("dead_bytes");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"key", b"value-0").unwrap();
+    for round in 1..=10 {
+        store
+            .insert(b"key", format!("value-{round}").as_bytes())
+            .unwrap();
+    }
+    // "value-0" to "value-9" are superseded, "value-10" is live.
+    let (insert, last) = (format::insert_len(3, 7), format::insert_len(3, 8));
+    assert_eq!(store.dead_bytes(), 10 * insert as u64);
+
+    store.remove(b"key").unwrap();
+    let dead = 10 * insert + last + format::remove_len(3);
+    assert_eq!(store.dead_bytes(), dead as u64);
+    drop(store);
+
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.dead_bytes(), dead as u64);
+    store.reduce(1 << 20).unwrap();
+    assert_eq!(store.dead_bytes(), 0);
+}
+
+fn overwritten_store(name: &str) -> Store {
+    let mut store = Store::open(&dir(name)).unwrap();
+    for round in 0..10 {
+        for i in 0..200 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    store
+}
+
+fn disk_bytes(store: &Store) -> u64 {
+    let files = std::fs::read_dir(store.base_dir()).unwrap();
+    let files = files.map(|entry| entry.unwrap().path());
+    let data = files.filter(|path| path.extension().is_some_and(|ext| ext == "dat"));
+    data.map(|path| std::fs::metadata(path).unwrap().len())
+        .sum()
+}
+
+#[test]
+fn vacuum_shrinks_the_store_to_its_live_records() {
+    let mut store = overwritten_store("vacuum");
+    let live: u64 = (0..200)
+        .map(|i| format::insert_len(key(i).len(), val(9, i).len()) as u64)
+        .sum();
+    assert!(disk_bytes(&store) > 9 * live);
+
+    store.vacuum().unwrap();
+    let header = format::FILE_HEADER_LEN as u64;
+    assert_eq!(disk_bytes(&store), live + header);
+    assert_eq!(store.dead_bytes(), 0);
+    assert!((0..200).all(|i| store.get(&key(i)).unwrap() == Some(val(9, i))));
+}
+
+#[test]
+fn reduce_reports_what_it_reclaimed() {
+    let mut store = overwritten_store("compaction_result");
+    let before = disk_bytes(&store);
+    let result = store.reduce(1 << 20).unwrap();
+    assert_eq!(result.input_records, 2000);
+    assert_eq!(result.output_records, 200);
+    assert_eq!(result.input_bytes, before);
+    assert_eq!(result.output_bytes, disk_bytes(&store));
+    assert!(result.reclaimed_bytes > 0);
+    assert_eq!(
+        result.reclaimed_bytes,
+        result.input_bytes - result.output_bytes
+    );
+}
+
+#[test]
+fn compact_into_copies_only_live_records() {
+    let mut store = overwritten_store("compact_into_src");
+    for i in 0..50 {
+        store.remove(&key(i)).unwrap();
+    }
+    let dst = dir("compact_into_dst");
+    let mut copy = store.compact_into(&dst).unwrap();
+    assert_eq!(copy.len(), 150);
+    assert_eq!(copy.disk_record_count().unwrap(), 150);
+    assert!(copy.iter_raw().all(|record| record.unwrap().2.is_insert()));
+    drop(copy);
+
+    let copy = Store::open(&dst).unwrap();
+    for i in 0..200 {
+        assert_eq!(copy.get(&key(i)).unwrap(), store.get(&key(i)).unwrap());
+    }
+    assert_eq!(store.len(), 150);
+
+    // Only into an empty store.
+    assert!(store.compact_into(&dst).is_err());
+}
+
+#[test]
+fn writes_after_a_compaction_go_to_the_new_active_file() {
+    for strategy in [CompactionStrategy::Full, CompactionStrategy::Leveled] {
+        let dir = dir(&format!("writes_after_compaction_{strategy:?}"));
+        let options = || StoreOptions::new().compaction(strategy);
+        let mut store = Store::open_with(&dir, options()).unwrap();
+        store.insert(b"a", b"1").unwrap();
+        let old = store.active_file_path();
+        store.compact().unwrap();
+        let new = store.active_file_path();
+        assert_ne!(old, new);
+        assert!(!old.exists());
+
+        store.insert(b"b", b"2").unwrap();
+        store.flush().unwrap();
+        let (_, location) = store.get_with_metadata(b"b").unwrap().unwrap();
+        let stem = new.file_stem().unwrap().to_string_lossy();
+        assert_eq!(location.file().to_string(), stem);
+        drop(store);
+        let store = Store::open_with(&dir, options()).unwrap();
+        assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+    }
+}
+
+#[test]
+fn file_ids_only_increase_across_compactions() {
+    let dir = dir("file_ids_increase");
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    let mut next = store.next_file_id();
+    let mut seen = file_ids(&mut store);
+    assert!(seen.iter().all(|&id| id < next.get()), "{seen:?} {next}");
+    for round in 0..4 {
+        for i in 0..300 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.compact().unwrap();
+        let ids = file_ids(&mut store);
+        let created: Vec<u64> = ids
+            .iter()
+            .copied()
+            .filter(|id| !seen.contains(id))
+            .collect();
+        assert!(!created.is_empty(), "round {round}: {ids:?}");
+        assert!(
+            created.iter().all(|&id| id >= next.get()),
+            "{created:?} {next}"
+        );
+        assert!(store.next_file_id() > next);
+        next = store.next_file_id();
+        assert!(ids.iter().all(|&id| id < next.get()), "{ids:?} {next}");
+        seen = ids;
+    }
+    store.reduce(1 << 20).unwrap();
+    assert!(file_ids(&mut store).iter().all(|&id| id >= next.get()));
+}
+
+#[test]
+fn merging_overlapping_runs_keeps_the_newest_record_of_each_key() {
+    let dir = dir("merge_runs");
+    let options = leveled()
+        .run_bytes(1 << 20)
+        .level0_runs(4)
+        .level_fanout(100);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let mut model = BTreeMap::new();
+    for round in 0..4 {
+        for i in util::mix((0..500).collect(), round as u64) {
+            if i % 7 == round {
+                store.remove(&key(i)).unwrap();
+                model.remove(&key(i));
+            } else if (i + round) % 3 != 0 {
+                store.insert(&key(i), &val(round, i)).unwrap();
+                model.insert(key(i), val(round, i));
+            }
+        }
+        store.compact().unwrap();
+    }
+    // The fourth run tipped level 0 over into a single run below it.
+    let records: Vec<_> = store.iter_raw().map(Result::unwrap).collect();
+    let ids: BTreeSet<_> = records.iter().map(|(id, _, _)| *id).collect();
+    assert_eq!(ids.len(), 1, "{ids:?}");
+    let merged: Vec<_> = records
+        .into_iter()
+        .map(|(_, _, record)| (record.key().to_vec(), record.val().unwrap().to_vec()))
+        .collect();
+    let expected: Vec<_> = model.into_iter().collect();
+    assert_eq!(merged, expected);
+}
+
+#[test]
+fn compaction_reclaims_a_range_delete_whole() {
+    for options in [leveled().level_fanout(100), StoreOptions::new()] {
+        let dir = dir("range_delete_reclaimed");
+        let mut store = Store::open_with(&dir, options.clone()).unwrap();
+        for i in 0..2000 {
+            store.insert(&key(i), &val(0, i)).unwrap();
+        }
+        store.compact().unwrap();
+
+        assert_eq!(store.remove_range(&key(100), &key(1900)).unwrap(), 1800);
+        store.insert(&key(500), &val(1, 500)).unwrap();
+        store.compact().unwrap();
+        let records = raw_records(&mut store);
+        assert_eq!(records.len(), 201);
+        assert_eq!(count(&records, &key(500)), 1);
+        assert!(records.iter().all(|(_, insert)| *insert));
+        assert_eq!(store.dead_bytes(), 0);
+
+        let keys: Vec<Vec<u8>> = (0..100).chain([500]).chain(1900..2000).map(key).collect();
+        check_keys(&store, &keys);
+        drop(store);
+        check_keys(&Store::open_with(&dir, options).unwrap(), &keys);
+    }
+}
+
+fn check_keys(store: &Store, keys: &[Vec<u8>]) {
+    let found: Vec<Vec<u8>> = store.keys().map(<[u8]>::to_vec).collect();
+    assert_eq!(found, keys);
+    assert_eq!(store.get(&key(499)).unwrap(), None);
+    assert_eq!(store.get(&key(500)).unwrap(), Some(val(1, 500)));
+    assert_eq!(store.get(&key(1900)).unwrap(), Some(val(0, 1900)));
+}
+
+#[test]
+fn readers_make_progress_while_a_reduce_merges() {
+    let dir = dir("concurrent_reduce");
+    let options = StoreOptions::new().compaction_rate_limit(200_000);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    for round in 0..4 {
+        for i in 0..2000 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    let store = RwLock::new(store);
+    let merging = AtomicBool::new(true);
+    let reads = AtomicUsize::new(0);
+
+    let pending = std::thread::scope(|scope| {
+        for reader in 0..2 {
+            let (store, merging, reads) = (&store, &merging, &reads);
+            scope.spawn(move || {
+                let mut i = reader;
+                while merging.load(Ordering::Acquire) {
+                    let store = store.read().unwrap();
+                    assert_eq!(store.get(&key(i % 2000)).unwrap(), Some(val(3, i % 2000)));
+                    reads.fetch_add(1, Ordering::Relaxed);
+                    i += 7;
+                }
+            });
+        }
+        let pending = store.read().unwrap().prepare_reduce(1 << 20).unwrap();
+        merging.store(false, Ordering::Release);
+        pending
+    });
+    // The merge writes about 75 KB at 200 KB/s.
+    assert!(reads.load(Ordering::Relaxed) > 100, "{reads:?}");
+
+    let mut store = store.into_inner().unwrap();
+    store.insert(&key(0), b"after").unwrap();
+    let result = store.finish_reduce(pending).unwrap();
+    assert!(result.reclaimed_bytes > 0);
+    assert_eq!(store.get(&key(0)).unwrap(), Some(b"after".to_vec()));
+    assert_eq!(store.get(&key(1)).unwrap(), Some(val(3, 1)));
+    assert_eq!(store.len(), 2000);
+    drop(store);
+    assert_eq!(
+        Store::open(&dir).unwrap().get(&key(0)).unwrap(),
+        Some(b"after".to_vec())
+    );
+}
+
+#[test]
+fn finish_reduce_fails_if_the_files_changed_meanwhile() {
+    let dir = dir("stale_reduce");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let pending = store.prepare_reduce(1 << 20).unwrap();
+    store.reduce(1 << 20).unwrap();
+    assert!(store.finish_reduce(pending).is_err());
+    assert_eq!(store.len(), 100);
+    assert_eq!(file_ids(&mut store).len(), 1);
+    drop(store);
+    assert_eq!(
+        Store::open(&dir).unwrap().get(&key(5)).unwrap(),
+        Some(val(0, 5))
+    );
+}
+
+#[test]
+fn empty_values_survive_compaction_as_present_keys() {
+    for options in [
+        StoreOptions::new(),
+        leveled(),
+        leveled().varint_lengths(true),
+    ] {
+        let dir = dir("empty_values");
+        let mut store = Store::open_with(&dir, options.clone()).unwrap();
+        store.insert(b"empty", b"").unwrap();
+        store.insert(b"removed", b"").unwrap();
+        store.remove(b"removed").unwrap();
+        store.insert(b"emptied", b"full").unwrap();
+        store.insert(b"emptied", b"").unwrap();
+        for i in 0..200 {
+            store.insert(&key(i), &val(0, i)).unwrap();
+        }
+        store.compact().unwrap();
+        store.reduce(1 << 20).unwrap();
+
+        let check = |store: &Store| {
+            assert_eq!(store.get(b"empty").unwrap(), Some(Vec::new()));
+            assert_eq!(store.get(b"emptied").unwrap(), Some(Vec::new()));
+            assert_eq!(store.get(b"removed").unwrap(), None);
+            assert_eq!(store.len(), 202);
+        };
+        check(&store);
+        let records: Vec<_> = store.iter_raw().map(Result::unwrap).collect();
+        let empty = records
+            .iter()
+            .find(|(_, _, record)| record.key() == b"empty");
+        assert_eq!(empty.unwrap().2.val(), Some(&b""[..]));
+        drop(store);
+        std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+        check(&Store::open_with(&dir, options).unwrap());
+    }
+}
+
+#[test]
+fn rate_limited_compactions_take_their_minimum_time() {
+    let rate = 200_000;
+    for strategy in [CompactionStrategy::Full, CompactionStrategy::Leveled] {
+        let dir = dir("rate_limit");
+        let options = StoreOptions::new()
+            .compaction(strategy)
+            .compaction_rate_limit(rate);
+        let mut store = Store::open_with(&dir, options).unwrap();
+        for i in 0..2000 {
+            store.insert(&key(i), &val(0, i)).unwrap();
+        }
+        // Everything is live, so the compaction writes it all out again.
+        let bytes = store.active_file_bytes();
+        let started = Instant::now();
+        store.compact().unwrap();
+        let elapsed = started.elapsed();
+        let minimum = Duration::from_secs_f64(0.9 * bytes as f64 / rate as f64);
+        assert!(
+            elapsed >= minimum,
+            "{strategy:?}: {elapsed:?} for {bytes} bytes"
+        );
+    }
+
+    let mut store = Store::open(&dir("no_rate_limit")).unwrap();
+    for i in 0..2000 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let result = store.reduce(1 << 20).unwrap();
+    let limited = Duration::from_secs_f64(result.output_bytes as f64 / rate as f64);
+    assert!(result.elapsed < limited, "{:?}", result.elapsed);
+}
+
+#[test]
+fn reduce_rejects_a_zero_limit_and_raises_tiny_ones() {
+    let dir = dir("reduce_limits");
+    let big = vec![7u8; 3 * yalskv::MIN_CHUNK_BYTES];
+    let fill = |store: &mut Store, round| {
+        for i in 0..300 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.insert(b"big", &big).unwrap();
+    };
+    let mut store = Store::open(&dir).unwrap();
+    fill(&mut store, 0);
+    match store.reduce(0) {
+        Err(yalskv::kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+        other => panic!("reduced with a zero limit: {other:?}"),
+    }
+    assert_eq!(store.disk_record_count().unwrap(), 301);
+
+    for limit in [1, 100, 1 << 30] {
+        fill(&mut store, limit as u32);
+        store.reduce(limit).unwrap();
+        assert_eq!(store.disk_record_count().unwrap(), 301, "{limit}");
+        assert_eq!(store.get(&key(5)).unwrap(), Some(val(limit as u32, 5)));
+        assert_eq!(store.get(b"big").unwrap(), Some(big.clone()));
+    }
+    drop(store);
+    assert_eq!(Store::open(&dir).unwrap().len(), 301);
+}
+
+#[test]
+fn compact_if_needed_waits_for_its_thresholds() {
+    let dir = dir("compact_if_needed");
+    let options = StoreOptions::new().compact_dead_ratio(0.5);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    assert!(!store.compact_if_needed().unwrap());
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.insert(&key(0), &val(1, 0)).unwrap();
+    assert!(!store.compact_if_needed().unwrap());
+    for i in 0..100 {
+        store.insert(&key(i), &val(2, i)).unwrap();
+    }
+    assert!(store.compact_if_needed().unwrap());
+    assert_eq!(store.dead_bytes(), 0);
+    assert!(!store.compact_if_needed().unwrap());
+    assert_eq!(store.get(&key(0)).unwrap(), Some(val(2, 0)));
+
+    let dir = common::dir("compact_if_needed_files");
+    let options = leveled().level0_runs(100).compact_max_files(3);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    for round in 0..3 {
+        store.insert(&key(round), &val(round, round)).unwrap();
+        store.compact().unwrap();
+    }
+    assert!(file_ids(&mut store).len() > 3);
+    assert!(store.compact_if_needed().unwrap());
+}
+
+/// Whether any directory shows up in `dir` while `reduce` runs.
+fn reduce_makes_a_dir(dir: &str, limit: usize) -> bool {
+    let options = StoreOptions::new().compaction_rate_limit(100_000);
+    let mut store = Store::open_with(dir, options).unwrap();
+    for i in util::mix((0..1000).collect(), 1) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let done = AtomicBool::new(false);
+    let seen = std::thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            let mut seen = false;
+            while !done.load(Ordering::Acquire) && !seen {
+                let mut entries = std::fs::read_dir(dir).unwrap();
+                seen = entries.any(|entry| entry.unwrap().file_type().unwrap().is_dir());
+            }
+            seen
+        });
+        store.reduce(limit).unwrap();
+        done.store(true, Ordering::Release);
+        watcher.join().unwrap()
+    });
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(
+        pairs,
+        (0..1000).map(|i| (key(i), val(0, i))).collect::<Vec<_>>()
+    );
+    assert_eq!(store.disk_record_count().unwrap(), 1000);
+    seen
+}
+
+#[test]
+fn a_single_chunk_reduce_sorts_in_memory() {
+    assert!(!reduce_makes_a_dir(&dir("single_chunk_reduce"), 1 << 20));
+    assert!(reduce_makes_a_dir(
+        &dir("split_reduce"),
+        yalskv::MIN_CHUNK_BYTES
+    ));
+}
+
+fn interrupted(result: yalskv::kv::Result<yalskv::CompactionResult>) {
+    match result {
+        Err(yalskv::kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::Interrupted),
+        other => panic!("compaction was not cancelled: {other:?}"),
+    }
+}
+
+#[test]
+fn cancelled_compactions_leave_the_store_as_it_was() {
+    let dir = dir("compact_cancelable");
+    let options = || {
+        StoreOptions::new()
+            .run_bytes(4 * 1024)
+            .compaction_rate_limit(200_000)
+    };
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    for round in 0..3 {
+        for i in 0..2000 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    let before = file_ids(&mut store);
+
+    let cancel = AtomicBool::new(false);
+    let started = Instant::now();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(100));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        interrupted(store.compact_cancelable(&cancel));
+    });
+    assert!(
+        started.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(file_ids(&mut store), before);
+    let names = std::fs::read_dir(&dir).unwrap();
+    for name in names.map(|entry| entry.unwrap().file_name()) {
+        let name = name.to_string_lossy();
+        assert!(name.ends_with(".dat") || name == "MANIFEST", "{name}");
+    }
+    for i in 0..2000 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(2, i)));
+    }
+
+    // Cancelled before it starts, nothing is written at all.
+    interrupted(store.compact_cancelable(&cancel));
+    assert_eq!(file_ids(&mut store), before);
+
+    cancel.store(false, Ordering::Relaxed);
+    drop(store);
+    let mut store = Store::open_with(&dir, StoreOptions::new().run_bytes(4 * 1024)).unwrap();
+    store.compact_cancelable(&cancel).unwrap();
+    assert!(file_ids(&mut store).iter().all(|id| !before.contains(id)));
+    for i in 0..2000 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(2, i)));
+    }
+}
+
+#[test]
+fn reduce_into_files_cuts_the_live_bytes_into_even_runs() {
+    for strategy in [CompactionStrategy::Full, CompactionStrategy::Leveled] {
+        let dir = dir(&format!("reduce_into_files_{strategy:?}"));
+        let options = || StoreOptions::new().compaction(strategy);
+        let mut store = Store::open_with(&dir, options()).unwrap();
+        for round in 0..3 {
+            for i in 0..2000 {
+                store.insert(&key(i), &val(round, i)).unwrap();
+            }
+        }
+        for i in 0..100 {
+            store.remove(&key(i)).unwrap();
+        }
+        assert_eq!(store.reduce_into_files(4).unwrap(), 4);
+        let stats = store.file_stats().unwrap();
+        let sizes: Vec<u64> = stats
+            .iter()
+            .filter(|stat| stat.total_records > 0)
+            .map(|stat| stat.total_bytes)
+            .collect();
+        assert_eq!(sizes.len(), 4, "{stats:?}");
+        let (min, max) = (sizes.iter().min().unwrap(), sizes.iter().max().unwrap());
+        assert!(max * 2 < min * 3, "{sizes:?}");
+        assert!(stats.iter().all(|stat| stat.dead_bytes == 0), "{stats:?}");
+        assert_eq!(store.dead_bytes(), 0);
+        assert_eq!(store.len(), 1900);
+        assert_eq!(store.get(&key(500)).unwrap(), Some(val(2, 500)));
+
+        store.insert(&key(1), &val(3, 1)).unwrap();
+        drop(store);
+        let mut store = Store::open_with(&dir, options()).unwrap();
+        assert_eq!(store.len(), 1901);
+        assert_eq!(store.reduce_into_files(1).unwrap(), 1);
+        assert_eq!(store.get(&key(1)).unwrap(), Some(val(3, 1)));
+        store.compact().unwrap();
+        assert_eq!(store.len(), 1901);
+        assert!(store.reduce_into_files(0).is_err());
+    }
+}
+
+#[test]
+fn reads_after_a_compaction_follow_the_index_to_the_new_files() {
+    for strategy in [CompactionStrategy::Full, CompactionStrategy::Leveled] {
+        let dir = dir(&format!("reads_after_compaction_{strategy:?}"));
+        let mut store = Store::open_with(&dir, StoreOptions::new().compaction(strategy)).unwrap();
+        for i in 0..100 {
+            store.insert(&key(i), &val(0, i)).unwrap();
+        }
+        let (_, old) = store.get_with_metadata(&key(7)).unwrap().unwrap();
+        let old_path = store.active_file_path();
+        store.compact().unwrap();
+
+        assert!(!old_path.exists());
+        let (value, new) = store.get_with_metadata(&key(7)).unwrap().unwrap();
+        assert_eq!(value, val(0, 7));
+        assert_ne!(new.file(), old.file());
+        assert!(file_ids(&mut store)
+            .iter()
+            .all(|&id| id != old.file().get()));
+        let mut records = store.iter_raw().map(Result::unwrap);
+        assert!(records.all(|(file, _, _)| file != old.file()));
+    }
+}
@@ -0,0 +1,138 @@
+//! Kills a child process in the middle of compactions and checks that the
+//! store reopens with all of its data. The child is this test binary, run
+//! with `CRASH_DIR` set so that only its `compact_in_child` test does work.
+
+mod common;
+
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use common::{dir, key, val};
+use yalskv::{CompactionStrategy, Store, StoreOptions};
+
+const KEYS: u32 = 3000;
+
+fn options(strategy: &str) -> StoreOptions {
+    let strategy = match strategy {
+        "leveled" => CompactionStrategy::Leveled,
+        "tiered" => CompactionStrategy::SizeTiered,
+        _ => CompactionStrategy::Full,
+    };
+    StoreOptions::new()
+        .compaction(strategy)
+        .run_bytes(8 * 1024)
+        .level0_runs(2)
+        .tier_min_runs(2)
+        .compaction_rate_limit(200_000)
+}
+
+#[test]
+fn compact_in_child() {
+    let (Ok(dir), Ok(strategy)) = (std::env::var("CRASH_DIR"), std::env::var("CRASH_STRATEGY"))
+    else {
+        return;
+    };
+    let mut store = Store::open_with(&dir, options(&strategy)).unwrap();
+    match std::env::var("CRASH_INTO") {
+        Ok(_) => {
+            for files in [4, 2, 5, 1] {
+                store.reduce_into_files(files).unwrap();
+            }
+        }
+        Err(_) => {
+            for _ in 0..4 {
+                store.compact().unwrap();
+            }
+        }
+    }
+}
+
+/// Three rounds over the same keys, compacted in between and synced.
+fn populate(dir: &str, strategy: &str, into: bool) {
+    let mut store = Store::open_with(dir, options(strategy)).unwrap();
+    for round in 0..3 {
+        for i in 0..KEYS {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.sync().unwrap();
+        match (round, into) {
+            (0, true) => drop(store.reduce_into_files(3).unwrap()),
+            (0 | 1, false) => store.compact().unwrap(),
+            _ => (),
+        }
+    }
+}
+
+fn spawn(dir: &str, strategy: &str, into: bool) -> Child {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command.args(["compact_in_child", "--exact", "--test-threads=1"]);
+    command
+        .env("CRASH_DIR", dir)
+        .env("CRASH_STRATEGY", strategy);
+    if into {
+        command.env("CRASH_INTO", "1");
+    }
+    command.spawn().unwrap()
+}
+
+/// Kills `child` once `ready` holds, or after `limit` at the latest.
+fn kill_when(mut child: Child, limit: Duration, ready: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !ready() && started.elapsed() < limit {
+        if child.try_wait().unwrap().is_some() {
+            return;
+        }
+        std::thread::sleep(Duration::from_micros(200));
+    }
+    let _ = child.kill();
+    child.wait().unwrap();
+}
+
+fn assert_recovered(dir: &str, strategy: &str, case: &str) {
+    let store = Store::open_with(dir, options(strategy)).unwrap();
+    assert_eq!(store.len(), KEYS as usize, "{case}");
+    for i in 0..KEYS {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(2, i)), "{case}");
+    }
+}
+
+#[test]
+fn killed_compactions_lose_nothing() {
+    for strategy in ["full", "leveled", "tiered"] {
+        for delay in [20, 150, 500] {
+            let dir = dir(&format!("crash_{strategy}"));
+            populate(&dir, strategy, false);
+            let child = spawn(&dir, strategy, false);
+            kill_when(child, Duration::from_millis(delay), || false);
+            assert_recovered(&dir, strategy, &format!("{strategy} after {delay}ms"));
+        }
+    }
+}
+
+#[test]
+fn killed_between_creating_and_filling_the_merged_file() {
+    let dir = dir("crash_reduce_output");
+    populate(&dir, "full", false);
+    let merging = || {
+        let files = std::fs::read_dir(&dir).unwrap();
+        let names = files.map(|entry| entry.unwrap().file_name());
+        names
+            .into_iter()
+            .any(|name| Path::new(&name).extension() == Some("reduce".as_ref()))
+    };
+    let child = spawn(&dir, "full", false);
+    kill_when(child, Duration::from_secs(10), merging);
+    assert_recovered(&dir, "full", "reduce output");
+}
+
+#[test]
+fn killed_reduce_into_files_loses_nothing() {
+    for delay in [20, 150, 300, 600, 900] {
+        let dir = dir("crash_reduce_into_files");
+        populate(&dir, "full", true);
+        let child = spawn(&dir, "full", true);
+        kill_when(child, Duration::from_millis(delay), || false);
+        assert_recovered(&dir, "full", &format!("reduce_into_files after {delay}ms"));
+    }
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "encryption")]
+
+mod common;
+
+use std::io::{Seek, SeekFrom, Write};
+
+use common::{dir, key, val};
+use yalskv::{kv, EncryptionKey, Store, StoreOptions};
+
+fn options(secret: u8) -> StoreOptions {
+    StoreOptions::new().encryption_key(EncryptionKey([secret; 32]))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+fn values_round_trip_encrypted_at_rest() {
+    let dir = dir("encryption");
+    let mut store = Store::open_with(&dir, options(7)).unwrap();
+    for round in 0..2 {
+        for i in 0..100 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    store.insert(b"empty", b"").unwrap();
+    assert_eq!(store.get(&key(42)).unwrap(), Some(val(1, 42)));
+    assert_eq!(store.get(b"empty").unwrap(), Some(Vec::new()));
+
+    let bytes = std::fs::read(store.active_file_path()).unwrap();
+    assert!(contains(&bytes, &key(42)));
+    assert!(!contains(&bytes, &val(1, 42)));
+
+    store.reduce(1 << 20).unwrap();
+    drop(store);
+    let store = Store::open_with(&dir, options(7)).unwrap();
+    for i in 0..100 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(1, i)));
+    }
+    let mut buffer = Vec::new();
+    let mut scanned = Vec::new();
+    store
+        .scan(&mut buffer, |k, v| scanned.push((k.to_vec(), v.to_vec())))
+        .unwrap();
+    assert_eq!(scanned.last(), Some(&(key(99), val(1, 99))));
+}
+
+#[test]
+fn tampered_values_fail_authentication() {
+    let dir = dir("encryption_tampered");
+    let mut store = Store::open_with(&dir, options(7)).unwrap();
+    store.insert(b"a", b"secret").unwrap();
+    store.insert(b"b", b"other").unwrap();
+    let (_, location) = store.get_with_metadata(b"a").unwrap().unwrap();
+    assert_eq!(location.length(), 12 + 6 + 16);
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(store.active_file_path())
+        .unwrap();
+    file.seek(SeekFrom::Start(location.offset() + 14)).unwrap();
+    file.write_all(b"X").unwrap();
+    drop(file);
+    match store.get(b"a") {
+        Err(kv::Error::Corrupt { file, .. }) => assert_eq!(file, location.file().get()),
+        other => panic!("tampered value read as {other:?}"),
+    }
+    assert_eq!(store.get(b"b").unwrap(), Some(b"other".to_vec()));
+}
+
+fn assert_rejected(result: kv::Result<Store>) {
+    match result {
+        Err(kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("opened with the wrong key"),
+    }
+}
+
+#[test]
+fn opening_needs_the_key_the_store_was_written_with() {
+    let dir = dir("encryption_key_check");
+    let mut store = Store::open_with(&dir, options(7)).unwrap();
+    store.insert(b"a", b"secret").unwrap();
+    drop(store);
+
+    assert_rejected(Store::open(&dir));
+    assert_rejected(Store::open_with(&dir, options(8)));
+    let store = Store::open_with(&dir, options(7)).unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"secret".to_vec()));
+    drop(store);
+
+    // Nor can a key be added to a store written in the clear.
+    let dir = common::dir("encryption_key_added");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"a", b"plain").unwrap();
+    drop(store);
+    assert_rejected(Store::open_with(&dir, options(7)));
+    assert_eq!(
+        Store::open(&dir).unwrap().get(b"a").unwrap(),
+        Some(b"plain".to_vec())
+    );
+}
@@ -0,0 +1,479 @@
+mod common;
+
+use common::dir;
+use yalskv::format::{self, ByteOrder, Encoding};
+use yalskv::{CompactionStrategy, Record, RecordKind, Store, StoreOptions};
+
+/// Decodes the insert at `at` in `bytes` field by field.
+fn decode_insert(bytes: &[u8], at: usize, encoding: Encoding) -> (Vec<u8>, Vec<u8>, usize) {
+    let mut at = at;
+    let mut field = || {
+        let (val, len) = encoding.get(&bytes[at..]).unwrap();
+        at += len;
+        val as usize
+    };
+    let (op, key_len, val_len) = (field(), field(), field());
+    assert_eq!(op as u64, format::INSERT);
+    let key = bytes[at..at + key_len].to_vec();
+    let val = bytes[at + key_len..at + key_len + val_len].to_vec();
+    (key, val, at + key_len)
+}
+
+#[test]
+fn insert_locations_point_at_the_value_bytes() {
+    for varint in [false, true] {
+        let dir = dir(&format!("insert_locations_{varint}"));
+        let options = StoreOptions::new().varint_lengths(varint);
+        let mut store = Store::open_with(&dir, options).unwrap();
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = [(0, 0), (1, 0), (2, 3), (5, 200), (300, 20)]
+            .iter()
+            .map(|(k, v)| (vec![b'k'; *k], vec![b'v'; *v]))
+            .collect();
+        for (key, val) in pairs.iter() {
+            store.insert(key, val).unwrap();
+        }
+        store.flush().unwrap();
+
+        let bytes = std::fs::read(store.active_file_path()).unwrap();
+        let encoding = store.file().encoding();
+        let starts: Vec<u64> = store.iter_raw().map(|record| record.unwrap().1).collect();
+        for ((key, val), start) in pairs.iter().zip(starts) {
+            let (found, location) = store.get_with_metadata(key).unwrap().unwrap();
+            assert_eq!(&found, val);
+            let (offset, length) = (location.offset() as usize, location.length() as usize);
+            assert_eq!(&bytes[offset..offset + length], &val[..]);
+
+            let (k, v, at) = decode_insert(&bytes, start as usize, encoding);
+            assert_eq!((&k, &v, at), (key, val, offset));
+        }
+    }
+}
+
+fn sample_records() -> Vec<Record> {
+    let long = vec![7u8; 300];
+    vec![
+        Record::Insert(b"key".to_vec(), b"val".to_vec()),
+        Record::Insert(Vec::new(), Vec::new()),
+        Record::Insert(long.clone(), long.clone()),
+        Record::Remove(b"key".to_vec()),
+        Record::Remove(Vec::new()),
+        Record::RemoveRange(b"a".to_vec(), long),
+    ]
+}
+
+#[test]
+fn record_len_matches_the_bytes_written() {
+    for varint in [false, true] {
+        let dir = dir(&format!("record_len_{varint}"));
+        let mut store = Store::open_with(&dir, StoreOptions::new().varint_lengths(varint)).unwrap();
+        let encoding = store.file().encoding();
+        for record in sample_records() {
+            let before = store.active_file_bytes();
+            let start = store.file().append_record(&record).unwrap();
+            assert_eq!(start, before);
+            let written = store.active_file_bytes() - before;
+            assert_eq!(written as usize, record.encoded_len(encoding), "{record:?}");
+            if !varint {
+                assert_eq!(written as usize, record.len(), "{record:?}");
+            }
+        }
+        store.flush().unwrap();
+        let len = std::fs::metadata(store.active_file_path()).unwrap().len();
+        assert_eq!(len, store.active_file_bytes());
+    }
+}
+
+#[test]
+fn record_is_empty_means_no_key_and_no_value() {
+    assert!(Record::Insert(Vec::new(), Vec::new()).is_empty());
+    assert!(Record::Remove(Vec::new()).is_empty());
+    assert!(!Record::Insert(Vec::new(), b"v".to_vec()).is_empty());
+    assert!(!Record::Insert(b"k".to_vec(), Vec::new()).is_empty());
+    assert!(!Record::Remove(b"k".to_vec()).is_empty());
+    let min = format::REMOVE_HEADER_LEN;
+    assert!(sample_records().iter().all(|record| record.len() >= min));
+}
+
+#[test]
+fn record_sizes_follow_from_the_format_constants() {
+    let insert = Record::Insert(b"key".to_vec(), b"value".to_vec());
+    assert_eq!(insert.len(), 3 * format::FIELD_LEN + 3 + 5);
+    assert_eq!(insert.len(), format::INSERT_HEADER_LEN + 3 + 5);
+    assert_eq!(insert.len(), format::insert_len(3, 5));
+
+    let remove = Record::Remove(b"key".to_vec());
+    assert_eq!(remove.len(), 2 * format::FIELD_LEN + 3);
+    assert_eq!(remove.len(), format::REMOVE_HEADER_LEN + 3);
+    assert_eq!(remove.len(), format::remove_len(3));
+}
+
+#[test]
+fn data_files_start_with_the_documented_header() {
+    let dir = dir("file_header");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"key", b"value").unwrap();
+    store.flush().unwrap();
+    let bytes = std::fs::read(store.active_file_path()).unwrap();
+    assert_eq!(&bytes[..4], &format::MAGIC);
+    assert_eq!(bytes[4], format::VERSION);
+
+    let header: [u8; format::FILE_HEADER_LEN] =
+        bytes[..format::FILE_HEADER_LEN].try_into().unwrap();
+    assert_eq!(
+        format::parse_file_header(&header).unwrap(),
+        Some((Encoding::default(), false))
+    );
+    let record = &bytes[format::FILE_HEADER_LEN..];
+    assert_eq!(record.len(), format::insert_len(3, 5));
+    assert_eq!(&record[..format::FIELD_LEN], &format::INSERT.to_be_bytes());
+    assert_eq!(&record[format::INSERT_HEADER_LEN..], b"keyvalue");
+}
+
+#[test]
+fn records_of_yields_only_one_kind() {
+    let dir = dir("records_of");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..10u8 {
+        store.insert(&[i], b"v").unwrap();
+    }
+    for i in 0..4u8 {
+        store.remove(&[i]).unwrap();
+    }
+    store.remove_range(&[6], &[8]).unwrap();
+
+    let file = store.file();
+    file.reset().unwrap();
+    assert_eq!(file.records_of(RecordKind::Insert).count(), 10);
+    file.reset().unwrap();
+    let removed: Vec<Record> = file.records_of(RecordKind::Remove).collect();
+    assert_eq!(
+        removed,
+        (0..4u8)
+            .map(|i| Record::Remove(vec![i]))
+            .collect::<Vec<_>>()
+    );
+    file.reset().unwrap();
+    assert_eq!(file.records_of(RecordKind::RemoveRange).count(), 1);
+}
+
+#[test]
+fn little_endian_files_declare_and_read_their_byte_order() {
+    let dir = dir("little_endian");
+    let options = || StoreOptions::new().byte_order(ByteOrder::Little);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    store.insert(b"key", b"value").unwrap();
+    store.insert(b"gone", b"soon").unwrap();
+    store.remove(b"gone").unwrap();
+    store.flush().unwrap();
+    assert_eq!(store.file().byte_order(), ByteOrder::Little);
+
+    let bytes = std::fs::read(store.active_file_path()).unwrap();
+    let header: [u8; format::FILE_HEADER_LEN] =
+        bytes[..format::FILE_HEADER_LEN].try_into().unwrap();
+    let little = Encoding::Fixed(ByteOrder::Little);
+    assert_eq!(
+        format::parse_file_header(&header).unwrap(),
+        Some((little, false))
+    );
+    let record = &bytes[format::FILE_HEADER_LEN..];
+    assert_eq!(&record[..format::FIELD_LEN], &format::INSERT.to_le_bytes());
+    assert_eq!(
+        &record[format::FIELD_LEN..2 * format::FIELD_LEN],
+        &3u64.to_le_bytes()
+    );
+    drop(store);
+
+    // The header wins over the options: a big-endian store reads it as is.
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(b"key").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(store.get(b"gone").unwrap(), None);
+    store.file().reset().unwrap();
+    assert_eq!(store.file().count(), 3);
+}
+
+#[test]
+fn record_count_keeps_the_read_position() {
+    let dir = dir("record_count");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..5u8 {
+        store.insert(&[i], b"v").unwrap();
+    }
+    let file = store.file();
+    file.reset().unwrap();
+    file.next().unwrap();
+    assert_eq!(file.peek_key().unwrap(), Some(&[1u8][..]));
+    assert_eq!(file.record_count().unwrap(), 5);
+    assert_eq!(file.next(), Some(Record::Insert(vec![1], b"v".to_vec())));
+
+    // A peeked record stays peeked.
+    file.peek_record().unwrap();
+    assert_eq!(file.record_count().unwrap(), 5);
+    assert_eq!(file.next(), Some(Record::Insert(vec![2], b"v".to_vec())));
+}
+
+const MAGNITUDES: [usize; 8] = [0, 1, 127, 128, 300, 16383, 16384, 70_000];
+
+#[test]
+fn varint_fields_round_trip() {
+    let values = [
+        0,
+        1,
+        127,
+        128,
+        16383,
+        16384,
+        1 << 35,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    for val in values {
+        let mut bytes = Vec::new();
+        Encoding::Varint.put(&mut bytes, val);
+        assert_eq!(bytes.len(), Encoding::Varint.field_len(val), "{val}");
+        assert_eq!(
+            Encoding::Varint.get(&bytes),
+            Some((val, bytes.len())),
+            "{val}"
+        );
+        assert_eq!(
+            Encoding::Varint.get(&bytes[..bytes.len() - 1]),
+            None,
+            "{val}"
+        );
+    }
+    assert_eq!(Encoding::Varint.field_len(127), 1);
+    assert_eq!(Encoding::Varint.field_len(128), 2);
+    assert_eq!(Encoding::Varint.field_len(u64::MAX), format::MAX_VARINT_LEN);
+    let overlong = [0xFF; format::MAX_VARINT_LEN];
+    assert_eq!(Encoding::Varint.get(&overlong), None);
+}
+
+#[test]
+fn varint_records_round_trip_across_length_magnitudes() {
+    let dir = dir("varint_magnitudes");
+    let options = || StoreOptions::new().varint_lengths(true);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    let mut expected = Vec::new();
+    for (i, &key_len) in MAGNITUDES.iter().enumerate() {
+        for &val_len in MAGNITUDES.iter() {
+            let mut key = vec![b'a' + i as u8; key_len];
+            key.extend_from_slice(&(val_len as u32).to_be_bytes());
+            let val = vec![(key_len + val_len) as u8; val_len];
+            store.insert(&key, &val).unwrap();
+            expected.push((key, val));
+        }
+    }
+    expected.sort();
+    let records: usize = expected
+        .iter()
+        .map(|(k, v)| Encoding::Varint.insert_len(k.len(), v.len()))
+        .sum();
+    assert_eq!(
+        store.active_file_bytes(),
+        (format::FILE_HEADER_LEN + records) as u64
+    );
+    let small = Encoding::Varint.insert_len(5, 1);
+    assert_eq!(small, 3 + 5 + 1);
+
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(pairs, expected);
+    store.reduce(1 << 20).unwrap();
+    drop(store);
+    let store = Store::open_with(&dir, options()).unwrap();
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn fixed_and_varint_files_are_read_side_by_side() {
+    let dir = dir("mixed_encodings");
+    let leveled = || {
+        let options = StoreOptions::new().compaction(CompactionStrategy::Leveled);
+        options.level0_runs(10)
+    };
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    for i in 0..100 {
+        store.insert(&common::key(i), &common::val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    drop(store);
+
+    // Runs written from here on are varint files.
+    let varint = || leveled().varint_lengths(true);
+    let mut store = Store::open_with(&dir, varint()).unwrap();
+    store.insert(b"", b"").unwrap();
+    for i in 50..150 {
+        store.insert(&common::key(i), &common::val(1, i)).unwrap();
+    }
+    store.remove(&common::key(0)).unwrap();
+    store.compact().unwrap();
+    store.insert(&common::key(10), &common::val(0, 10)).unwrap();
+    drop(store);
+
+    let check = |store: &Store| {
+        assert_eq!(store.len(), 150);
+        assert_eq!(store.get(&common::key(0)).unwrap(), None);
+        assert_eq!(
+            store.get(&common::key(10)).unwrap(),
+            Some(common::val(0, 10))
+        );
+        assert_eq!(
+            store.get(&common::key(60)).unwrap(),
+            Some(common::val(1, 60))
+        );
+        assert_eq!(store.get(b"").unwrap(), Some(Vec::new()));
+    };
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    check(&store);
+    let mut encodings: Vec<Encoding> = Vec::new();
+    for stat in store.file_stats().unwrap() {
+        let path = format!("{dir}/{}.dat", stat.file_id);
+        let header: [u8; format::FILE_HEADER_LEN] = std::fs::read(path).unwrap()
+            [..format::FILE_HEADER_LEN]
+            .try_into()
+            .unwrap();
+        encodings.push(format::parse_file_header(&header).unwrap().unwrap().0);
+    }
+    assert!(
+        encodings.contains(&Encoding::Fixed(ByteOrder::Big)),
+        "{encodings:?}"
+    );
+    assert!(encodings.contains(&Encoding::Varint), "{encodings:?}");
+
+    store.reduce(1 << 20).unwrap();
+    check(&store);
+    drop(store);
+    check(&Store::open_with(&dir, varint()).unwrap());
+}
+
+#[test]
+fn appended_records_read_back_in_order() {
+    let dir = dir("append_record");
+    let mut store = Store::open(&dir).unwrap();
+    let records = vec![
+        Record::Insert(b"a".to_vec(), b"1".to_vec()),
+        Record::Remove(b"b".to_vec()),
+        Record::Insert(b"".to_vec(), b"".to_vec()),
+        Record::RemoveRange(b"c".to_vec(), b"e".to_vec()),
+        Record::Insert(b"a".to_vec(), b"2".to_vec()),
+    ];
+    let file = store.file();
+    let encoding = file.encoding();
+    let mut expected = format::FILE_HEADER_LEN as u64;
+    for record in records.iter() {
+        let offset = file.append_record(record).unwrap();
+        assert_eq!(offset, expected);
+        expected += record.encoded_len(encoding) as u64;
+    }
+    file.reset().unwrap();
+    let read: Vec<Record> = file.collect();
+    assert_eq!(read, records);
+    assert_eq!(store.active_file_bytes(), expected);
+    assert_eq!(store.get(b"a").unwrap(), None);
+
+    drop(store);
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(b"").unwrap(), Some(Vec::new()));
+    assert_eq!(store.len(), 2);
+}
+
+#[test]
+fn records_tell_their_kind() {
+    let insert = Record::Insert(b"k".to_vec(), b"v".to_vec());
+    let remove = Record::Remove(b"k".to_vec());
+    let range = Record::RemoveRange(b"a".to_vec(), b"b".to_vec());
+    assert_eq!(insert.kind(), RecordKind::Insert);
+    assert_eq!(remove.kind(), RecordKind::Remove);
+    assert_eq!(range.kind(), RecordKind::RemoveRange);
+    assert!(insert.is_insert() && !insert.is_remove());
+    assert!(remove.is_remove() && !remove.is_insert());
+    assert!(range.is_remove() && !range.is_insert());
+}
+
+#[test]
+fn truncated_files_read_up_to_the_cut() {
+    let dir = dir("truncate_file");
+    let mut store = Store::open(&dir).unwrap();
+    let file = store.file();
+    let records: Vec<Record> = (0..5u8)
+        .map(|i| Record::Insert(vec![i], vec![i; 3]))
+        .collect();
+    let offsets: Vec<u64> = records
+        .iter()
+        .map(|record| file.append_record(record).unwrap())
+        .collect();
+
+    file.reset().unwrap();
+    assert_eq!(file.peek_key().unwrap(), Some(&[0u8][..]));
+    file.truncate(offsets[2]).unwrap();
+    file.reset().unwrap();
+    let read: Vec<Record> = file.by_ref().collect();
+    assert_eq!(read, records[..2]);
+    assert_eq!(file.peek_key().unwrap(), None);
+
+    let appended = Record::Remove(vec![9]);
+    assert_eq!(file.append_record(&appended).unwrap(), offsets[2]);
+    file.reset().unwrap();
+    let read: Vec<Record> = file.by_ref().collect();
+    assert_eq!(read, [&records[..2], &[appended]].concat());
+
+    let below_header = format::FILE_HEADER_LEN as u64 - 1;
+    let e = file.truncate(below_header).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    drop(store);
+    assert_eq!(Store::open(&dir).unwrap().len(), 2);
+}
+
+#[test]
+fn peeking_a_key_leaves_the_record_to_read() {
+    let dir = dir("peek_key");
+    let mut store = Store::open(&dir).unwrap();
+    let records = [
+        Record::Insert(b"a".to_vec(), vec![1; 1000]),
+        Record::Remove(b"b".to_vec()),
+        Record::RemoveRange(b"c".to_vec(), b"d".to_vec()),
+        Record::Insert(b"".to_vec(), b"".to_vec()),
+    ];
+    let file = store.file();
+    for record in records.iter() {
+        file.append_record(record).unwrap();
+    }
+    file.reset().unwrap();
+    for record in records.iter() {
+        assert_eq!(file.peek_key().unwrap(), Some(record.key()));
+        assert_eq!(file.peek_key().unwrap(), Some(record.key()));
+        assert_eq!(&file.read_record().unwrap(), record);
+    }
+    assert_eq!(file.peek_key().unwrap(), None);
+}
+
+#[test]
+fn records_order_by_key_then_kind_then_contents() {
+    let insert = |key: &[u8], val: &[u8]| Record::Insert(key.to_vec(), val.to_vec());
+    let remove = |key: &[u8]| Record::Remove(key.to_vec());
+    let range = |start: &[u8], end: &[u8]| Record::RemoveRange(start.to_vec(), end.to_vec());
+    assert_eq!(insert(b"a", b"1"), insert(b"a", b"1"));
+    assert_ne!(insert(b"a", b"1"), remove(b"a"));
+
+    let mut records = vec![
+        range(b"a", b"c"),
+        remove(b"a"),
+        insert(b"b", b"0"),
+        insert(b"a", b"2"),
+        insert(b"a", b"1"),
+        range(b"a", b"b"),
+    ];
+    records.sort();
+    let expected = vec![
+        insert(b"a", b"1"),
+        insert(b"a", b"2"),
+        remove(b"a"),
+        range(b"a", b"b"),
+        range(b"a", b"c"),
+        insert(b"b", b"0"),
+    ];
+    assert_eq!(records, expected);
+    let set: std::collections::BTreeSet<_> = records.into_iter().chain(expected).collect();
+    assert_eq!(set.len(), 6);
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use std::ops::Bound;
+
+use common::{dir, key, val};
+use yalskv::{kv, util, Iter, Store, StoreOptions};
+
+fn shuffled_store(name: &str, count: u32) -> Store {
+    let mut store = Store::open(&dir(name)).unwrap();
+    for i in util::mix((0..count).collect(), 42) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store
+}
+
+#[test]
+fn iteration_is_sorted_before_any_compaction() {
+    let store = shuffled_store("sorted_iteration", 500);
+    let keys: Vec<Vec<u8>> = store.iter().map(|pair| pair.unwrap().0).collect();
+    assert_eq!(keys, (0..500).map(key).collect::<Vec<_>>());
+    let keys: Vec<&[u8]> = store.keys().collect();
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let (start, end) = (key(100), key(200));
+    let range = store.range(Bound::Excluded(&start[..]), Bound::Included(&end[..]));
+    let pairs: Vec<(Vec<u8>, Vec<u8>)> = range.map(Result::unwrap).collect();
+    let expected: Vec<_> = (101..=200).map(|i| (key(i), val(0, i))).collect();
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn reversed_ranges_are_empty() {
+    let store = shuffled_store("reversed_ranges", 10);
+    let (a, b) = (key(2), key(5));
+    let bounds = [
+        (Bound::Included(&b[..]), Bound::Excluded(&a[..])),
+        (Bound::Included(&b[..]), Bound::Included(&a[..])),
+        (Bound::Excluded(&a[..]), Bound::Excluded(&a[..])),
+        (Bound::Included(&a[..]), Bound::Excluded(&a[..])),
+    ];
+    for (start, end) in bounds {
+        assert_eq!(store.range(start, end).count(), 0, "{start:?} {end:?}");
+    }
+    let single = store.range(Bound::Included(&a[..]), Bound::Included(&a[..]));
+    assert_eq!(single.count(), 1);
+}
+
+#[test]
+fn min_and_max_keys_come_from_the_index() {
+    let dir = dir("min_max");
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.min_key(), None);
+    assert_eq!(store.max_key(), None);
+
+    store.insert(b"m", b"1").unwrap();
+    assert_eq!(store.min_key(), Some(&b"m"[..]));
+    assert_eq!(store.max_key(), Some(&b"m"[..]));
+
+    store.insert(b"z", b"2").unwrap();
+    store.insert(b"a", b"3").unwrap();
+    assert_eq!(store.min_key(), Some(&b"a"[..]));
+    assert_eq!(store.max_key(), Some(&b"z"[..]));
+
+    store.remove(b"a").unwrap();
+    assert_eq!(store.min_key(), Some(&b"m"[..]));
+}
+
+#[test]
+fn pages_cover_the_store_once() {
+    let store = shuffled_store("scan_page", 10);
+    let mut seen = Vec::new();
+    let mut pages = 0;
+    let mut cursor: Option<Vec<u8>> = None;
+    loop {
+        let (page, next) = store.scan_page(cursor.as_deref(), 3).unwrap();
+        assert!(page.len() <= 3);
+        pages += 1;
+        seen.extend(page);
+        match next {
+            Some(next) => {
+                assert_eq!(Some(&next), seen.last().map(|(key, _)| key));
+                cursor = Some(next);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(pages, 4);
+    let expected: Vec<_> = (0..10).map(|i| (key(i), val(0, i))).collect();
+    assert_eq!(seen, expected);
+
+    let (page, next) = store.scan_page(Some(&key(9)), 3).unwrap();
+    assert!(page.is_empty());
+    assert_eq!(next, None);
+}
+
+#[test]
+fn for_loops_see_the_latest_live_values() {
+    let mut store = shuffled_store("into_iter", 20);
+    for i in 0..10 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.remove(&key(15)).unwrap();
+
+    let mut pairs = Vec::new();
+    for entry in &mut store {
+        pairs.push(entry.unwrap());
+    }
+    let expected: Vec<_> = (0..20)
+        .filter(|&i| i != 15)
+        .map(|i| (key(i), val(u32::from(i < 10), i)))
+        .collect();
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn scan_reuses_one_value_buffer() {
+    let store = shuffled_store("scan", 1000);
+    let mut buffer = Vec::with_capacity(64);
+    let capacity = buffer.capacity();
+    let mut count = 0;
+    store
+        .scan(&mut buffer, |key, val| {
+            assert_eq!(key, common::key(count));
+            assert_eq!(val, common::val(0, count));
+            count += 1;
+        })
+        .unwrap();
+    assert_eq!(count, 1000);
+    assert_eq!(buffer.capacity(), capacity);
+}
+
+#[test]
+fn count_prefix_counts_each_namespace() {
+    let mut store = Store::open(&dir("count_prefix")).unwrap();
+    for i in 0..30u32 {
+        store.insert(format!("user/{i}").as_bytes(), b"u").unwrap();
+    }
+    for i in 0..12u32 {
+        store.insert(format!("order/{i}").as_bytes(), b"o").unwrap();
+    }
+    store.insert(b"user", b"bare").unwrap();
+    store.insert(&[0xFF, 0xFF], b"top").unwrap();
+    store.insert(&[0xFF, 0xFF, 0], b"top").unwrap();
+    store.remove(b"user/3").unwrap();
+
+    assert_eq!(store.count_prefix(b"user/"), 29);
+    assert_eq!(store.count_prefix(b"user/1"), 11);
+    assert_eq!(store.count_prefix(b"order/"), 12);
+    assert_eq!(store.count_prefix(b"user"), 30);
+    assert_eq!(store.count_prefix(b"none/"), 0);
+    assert_eq!(store.count_prefix(&[0xFF]), 2);
+    assert_eq!(store.count_prefix(b""), store.len());
+}
+
+#[test]
+fn sorted_records_follow_a_reduce() {
+    let mut store = shuffled_store("sorted_records", 500);
+    store.remove(&key(7)).unwrap();
+    store.reduce(1 << 20).unwrap();
+    let records: Vec<_> = store.sorted_records().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 499);
+    assert!(records
+        .windows(2)
+        .all(|pair| pair[0].key() <= pair[1].key()));
+    assert!(records.iter().all(|record| record.is_insert()));
+
+    store.insert(&key(0), b"again").unwrap();
+    let last = store.sorted_records().last().unwrap();
+    assert!(matches!(last, Err(kv::Error::Unknown(_))), "{last:?}");
+}
+
+fn reversed() -> StoreOptions {
+    StoreOptions::new().comparator("reversed", Box::new(|a: &[u8], b: &[u8]| b.cmp(a)))
+}
+
+fn keys_of(iter: Iter<'_>) -> Vec<Vec<u8>> {
+    iter.map(|pair| pair.unwrap().0).collect()
+}
+
+#[test]
+fn a_custom_comparator_orders_scans_and_compactions() {
+    let dir = dir("comparator");
+    let mut store = Store::open_with(&dir, reversed()).unwrap();
+    for i in util::mix((0..10).collect(), 5) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let descending: Vec<_> = (0..10).rev().map(key).collect();
+    assert_eq!(keys_of(store.iter()), descending);
+    assert_eq!(store.min_key(), Some(&key(9)[..]));
+    assert_eq!(store.max_key(), Some(&key(0)[..]));
+
+    let (high, low) = (key(8), key(3));
+    let range = store.range(Bound::Included(&high[..]), Bound::Excluded(&low[..]));
+    assert_eq!(keys_of(range), (4..=8).rev().map(key).collect::<Vec<_>>());
+    // Bounds the wrong way round under the comparator hold nothing.
+    let range = store.range(Bound::Included(&low[..]), Bound::Excluded(&high[..]));
+    assert!(keys_of(range).is_empty());
+    assert_eq!(
+        keys_of(store.iter_from(&low)),
+        (0..=3).rev().map(key).collect::<Vec<_>>()
+    );
+
+    store.reduce(1 << 20).unwrap();
+    let sorted: Vec<_> = store
+        .sorted_records()
+        .map(|r| r.unwrap().key().to_vec())
+        .collect();
+    assert_eq!(sorted, descending);
+    drop(store);
+
+    let store = Store::open_with(&dir, reversed()).unwrap();
+    assert_eq!(keys_of(store.iter()), descending);
+    drop(store);
+    match Store::open(&dir) {
+        Err(kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("opened a reversed store bytewise"),
+    }
+}
+
+#[test]
+fn a_custom_order_follows_every_change_to_the_keys() {
+    let dir = dir("comparator_updates");
+    let options = || reversed().compaction(yalskv::CompactionStrategy::Leveled);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    let mut live = std::collections::BTreeSet::new();
+    let check = |store: &Store, live: &std::collections::BTreeSet<u32>| {
+        let expected: Vec<_> = live.iter().rev().map(|&i| key(i)).collect();
+        assert_eq!(keys_of(store.iter()), expected);
+        assert_eq!(store.min_key(), expected.first().map(Vec::as_slice));
+        assert_eq!(store.max_key(), expected.last().map(Vec::as_slice));
+        let (page, _) = store.scan_page(Some(&key(150)), 5).unwrap();
+        let after: Vec<_> = expected.iter().filter(|k| **k < key(150)).take(5).collect();
+        assert_eq!(page.iter().map(|(k, _)| k).collect::<Vec<_>>(), after);
+    };
+    for i in util::mix((0..300).collect(), 9) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+        live.insert(i);
+    }
+    for i in (0..300).step_by(7) {
+        store.remove(&key(i)).unwrap();
+        live.remove(&i);
+    }
+    // Under the reversed order the range runs from the higher key down.
+    store.remove_range(&key(250), &key(200)).unwrap();
+    live.retain(|i| !(201..=250).contains(i));
+    store.insert(&key(210), &val(1, 210)).unwrap();
+    live.insert(210);
+    check(&store, &live);
+
+    store.compact().unwrap();
+    check(&store, &live);
+    store.reduce(1 << 20).unwrap();
+    check(&store, &live);
+    store.checkpoint().unwrap();
+    drop(store);
+    let store = Store::open_with(&dir, options()).unwrap();
+    check(&store, &live);
+    drop(store);
+    std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+    let store = Store::open_with(&dir, options()).unwrap();
+    check(&store, &live);
+}
+
+#[test]
+fn iter_from_starts_at_the_first_key_not_before_start() {
+    let mut store = Store::open(&dir("iter_from")).unwrap();
+    for i in (0..100).step_by(2) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let evens = |from| (from..100).step_by(2).map(key).collect::<Vec<_>>();
+    assert_eq!(keys_of(store.iter_from(&key(50))), evens(50));
+    assert_eq!(keys_of(store.iter_from(&key(51))), evens(52));
+    assert_eq!(keys_of(store.iter_from(b"")), evens(0));
+    assert_eq!(store.iter_from(&key(99)).count(), 0);
+
+    let mut store = Store::open_with(&dir("iter_from_reversed"), reversed()).unwrap();
+    for i in 0..10 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let expected: Vec<_> = (0..=4).rev().map(key).collect();
+    assert_eq!(keys_of(store.iter_from(&key(4))), expected);
+}
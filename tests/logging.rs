@@ -0,0 +1,100 @@
+#![cfg(feature = "logging")]
+
+mod common;
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use common::{dir, key, val};
+use log::{Level, Log, Metadata, Record};
+use yalskv::{CompactionStrategy, Store, StoreOptions};
+
+static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = record.args().to_string();
+        RECORDS.lock().unwrap().push((record.level(), message));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Messages logged at `level` since the last call.
+fn take(level: Level) -> Vec<String> {
+    let mut records = RECORDS.lock().unwrap();
+    let messages = records.iter().filter(|(l, _)| *l == level);
+    let messages = messages.map(|(_, message)| message.clone()).collect();
+    records.clear();
+    messages
+}
+
+fn populate(store: &mut Store) {
+    for round in 0..2 {
+        for i in 0..200 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+}
+
+// One test, so that no other test logs into the capture meanwhile.
+#[test]
+fn lifecycle_points_are_logged() {
+    log::set_logger(&Capture).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let dir = dir("logging");
+    let mut store = Store::open(&dir).unwrap();
+    let opened = take(Level::Info);
+    assert!(opened[0].starts_with("opened "), "{opened:?}");
+    assert!(opened[0].ends_with(": 1 files, 0 keys"), "{opened:?}");
+
+    populate(&mut store);
+    store.compact().unwrap();
+    let compaction = take(Level::Info);
+    assert!(
+        compaction[0].starts_with("reduce started: "),
+        "{compaction:?}"
+    );
+    assert!(
+        compaction[1].starts_with("reduce finished: "),
+        "{compaction:?}"
+    );
+
+    let options = StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(4 * 1024)
+        .level0_runs(1);
+    let leveled = common::dir("logging_leveled");
+    let mut store = Store::open_with(&leveled, options).unwrap();
+    populate(&mut store);
+    take(Level::Info);
+    store.compact().unwrap();
+    let compaction = take(Level::Info);
+    assert!(
+        compaction[0].starts_with("Leveled compaction started: "),
+        "{compaction:?}"
+    );
+    let finished = compaction.last().unwrap();
+    assert!(
+        finished.starts_with("Leveled compaction finished: reclaimed "),
+        "{compaction:?}"
+    );
+
+    let path = store.active_file_path();
+    drop(store);
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(&[1, 0]).unwrap();
+    take(Level::Warn);
+    Store::open(&leveled).unwrap();
+    let warnings = take(Level::Warn);
+    assert!(warnings
+        .iter()
+        .any(|warning| warning.starts_with("truncating torn write")));
+}
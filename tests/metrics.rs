@@ -0,0 +1,48 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::dir;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use yalskv::Store;
+
+fn snapshot(snapshotter: &Snapshotter) -> HashMap<String, DebugValue> {
+    let metrics = snapshotter.snapshot().into_vec().into_iter();
+    let metrics = metrics.map(|(key, _, _, value)| (key.key().name().to_string(), value));
+    metrics.collect()
+}
+
+// One test, as the recorder is global to the process.
+#[test]
+fn writes_and_reads_are_counted() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    recorder.install().unwrap();
+
+    let mut store = Store::open(&dir("metrics")).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"22").unwrap();
+    store.get(b"a").unwrap();
+    store.get(b"absent").unwrap();
+    store.remove(b"a").unwrap();
+
+    let metrics = snapshot(&snapshotter);
+    assert_eq!(metrics["yalskv_inserts"], DebugValue::Counter(2));
+    assert_eq!(metrics["yalskv_removes"], DebugValue::Counter(1));
+    assert_eq!(metrics["yalskv_lookups"], DebugValue::Counter(2));
+    match &metrics["yalskv_value_bytes"] {
+        DebugValue::Histogram(values) => {
+            let values: Vec<f64> = values.iter().map(|value| value.into_inner()).collect();
+            assert_eq!(values, vec![1.0, 2.0]);
+        }
+        other => panic!("value bytes recorded as {other:?}"),
+    }
+    assert!(matches!(
+        &metrics["yalskv_lookup_seconds"],
+        DebugValue::Histogram(_)
+    ));
+    assert_eq!(metrics["yalskv_index_keys"], DebugValue::Gauge(1.0.into()));
+    assert_eq!(metrics["yalskv_files"], DebugValue::Gauge(1.0.into()));
+}
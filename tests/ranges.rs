@@ -0,0 +1,184 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::{dir, key, val};
+use yalskv::{format, CompactionStrategy, Store, StoreOptions};
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn check(store: &Store, model: &Model, keys: u32) {
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    let expected: Vec<_> = model.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    assert_eq!(pairs, expected);
+    for i in 0..keys {
+        assert_eq!(store.get(&key(i)).unwrap(), model.get(&key(i)).cloned());
+    }
+}
+
+#[test]
+fn range_tombstones_hide_the_keys_they_cover() {
+    let dir = dir("range_lookups");
+    let mut store = Store::open(&dir).unwrap();
+    let mut model = Model::new();
+    for i in 0..1000 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+        model.insert(key(i), val(0, i));
+    }
+    let len = store.active_file_bytes();
+    assert_eq!(store.remove_range(&key(100), &key(900)).unwrap(), 800);
+    let tombstone = format::Encoding::default().remove_range_len(6, 6) as u64;
+    assert_eq!(store.active_file_bytes(), len + tombstone);
+    model.retain(|k, _| !(key(100)..key(900)).contains(k));
+    store.insert(&key(500), &val(1, 500)).unwrap();
+    model.insert(key(500), val(1, 500));
+    assert_eq!(store.remove_range(&key(2000), &key(3000)).unwrap(), 0);
+    assert_eq!(store.remove_range(&key(900), &key(100)).unwrap(), 0);
+    check(&store, &model, 1000);
+    assert_eq!(store.count_prefix(b"k001"), 0);
+
+    drop(store);
+    check(&Store::open(&dir).unwrap(), &model, 1000);
+    let mut store = Store::open(&dir).unwrap();
+    store.checkpoint().unwrap();
+    drop(store);
+    assert!(Store::open_report(&dir).unwrap().checkpoint);
+    check(&Store::open(&dir).unwrap(), &model, 1000);
+    std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+    let mut store = Store::open(&dir).unwrap();
+    check(&store, &model, 1000);
+
+    store.reduce(4096).unwrap();
+    check(&store, &model, 1000);
+    assert_eq!(store.disk_record_count().unwrap(), model.len());
+    drop(store);
+    check(&Store::open(&dir).unwrap(), &model, 1000);
+}
+
+/// Random writes and range deletes, compacted after every round and
+/// checked against a model, reopening now and then.
+fn compact_with_ranges(strategy: CompactionStrategy) {
+    let dir = dir(&format!("ranges_{strategy:?}"));
+    let options = || {
+        let options = StoreOptions::new().compaction(strategy).run_bytes(2000);
+        options.level0_runs(2)
+    };
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    let mut model = Model::new();
+    let mut x: u64 = 7;
+    let mut next = || {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        x >> 20
+    };
+    for round in 0..40 {
+        for _ in 0..50 {
+            let i = (next() % 1000) as u32;
+            store.insert(&key(i), &val(round, i)).unwrap();
+            model.insert(key(i), val(round, i));
+        }
+        let start = (next() % 1000) as u32;
+        let end = start + (next() % 200) as u32;
+        let (start, end) = (key(start), key(end));
+        let covered: Vec<_> = model
+            .range(start.clone()..end.clone())
+            .map(|(k, _)| k.clone())
+            .collect();
+        assert_eq!(store.remove_range(&start, &end).unwrap(), covered.len());
+        for key in covered {
+            model.remove(&key);
+        }
+        store.compact().unwrap();
+        check(&store, &model, 1200);
+        if round % 7 == 0 {
+            drop(store);
+            store = Store::open_with(&dir, options()).unwrap();
+            check(&store, &model, 1200);
+        }
+    }
+    store.reduce(1 << 20).unwrap();
+    check(&store, &model, 1200);
+    drop(store);
+    check(&Store::open_with(&dir, options()).unwrap(), &model, 1200);
+}
+
+#[test]
+fn leveled_compaction_honors_range_tombstones() {
+    compact_with_ranges(CompactionStrategy::Leveled);
+}
+
+#[test]
+fn tiered_compaction_honors_range_tombstones() {
+    compact_with_ranges(CompactionStrategy::SizeTiered);
+}
+
+#[test]
+fn full_compaction_honors_range_tombstones() {
+    compact_with_ranges(CompactionStrategy::Full);
+}
+
+#[test]
+fn dead_bytes_agree_with_a_replay_after_range_deletes() {
+    for strategy in [CompactionStrategy::Leveled, CompactionStrategy::SizeTiered] {
+        let dir = dir(&format!("range_dead_bytes_{strategy:?}"));
+        let options = || StoreOptions::new().compaction(strategy);
+        let mut store = Store::open_with(&dir, options()).unwrap();
+        for i in 0..50 {
+            store.insert(&[i], &[i; 20]).unwrap();
+        }
+        store.remove_range(&[10], &[40]).unwrap();
+        for i in 0..5 {
+            store.insert(&[i], &[i; 20]).unwrap();
+        }
+        store.compact().unwrap();
+        let stats = store.file_stats().unwrap();
+        let dead: u64 = stats.iter().map(|stat| stat.dead_bytes).sum();
+        assert_eq!(store.dead_bytes(), dead, "{strategy:?}");
+        drop(store);
+
+        std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+        let store = Store::open_with(&dir, options()).unwrap();
+        assert_eq!(store.dead_bytes(), dead, "{strategy:?}");
+    }
+}
+
+fn headerless_insert(key: &[u8], val: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for field in [format::INSERT, key.len() as u64, val.len() as u64] {
+        bytes.extend_from_slice(&field.to_be_bytes());
+    }
+    bytes.extend_from_slice(key);
+    bytes.extend_from_slice(val);
+    bytes
+}
+
+#[test]
+fn range_deletes_work_on_a_headerless_log() {
+    let dir = dir("range_headerless");
+    let log = format!("{dir}/00000000000000000001.dat");
+    let records = [(b"a", b"1"), (b"b", b"2"), (b"c", b"3"), (b"a", b"4")];
+    let bytes: Vec<u8> = records
+        .iter()
+        .flat_map(|(key, val)| headerless_insert(*key, *val))
+        .collect();
+    std::fs::write(&log, bytes).unwrap();
+
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"4".to_vec()));
+    assert_eq!(store.remove_range(b"b", b"c").unwrap(), 1);
+    assert_eq!(store.get(b"a").unwrap(), Some(b"4".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), None);
+    assert_eq!(store.get(b"c").unwrap(), Some(b"3".to_vec()));
+    store.insert(b"d", b"5").unwrap();
+    drop(store);
+
+    // The log was copied behind a header under a new id.
+    assert!(!std::path::Path::new(&log).exists());
+    std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+    let store = Store::open(&dir).unwrap();
+    let keys: Vec<&[u8]> = store.keys().collect();
+    assert_eq!(keys, vec![&b"a"[..], &b"c"[..], &b"d"[..]]);
+    assert_eq!(store.get(b"a").unwrap(), Some(b"4".to_vec()));
+    assert_eq!(store.get(b"d").unwrap(), Some(b"5".to_vec()));
+}
@@ -0,0 +1,623 @@
+mod common;
+
+use common::{dir, key, val};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use yalskv::format::{self, Encoding};
+use yalskv::{kv, CompactionStrategy, Store, StoreOptions};
+
+fn checkpointed_store(name: &str, count: u32) -> String {
+    let dir = dir(name);
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..count {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.remove(&key(0)).unwrap();
+    store.checkpoint().unwrap();
+    dir
+}
+
+fn assert_contents(store: &Store, count: u32, round: impl Fn(u32) -> u32) {
+    assert_eq!(store.len(), count as usize - 1);
+    assert_eq!(store.get(&key(0)).unwrap(), None);
+    for i in 1..count {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(round(i), i)));
+    }
+}
+
+#[test]
+fn open_loads_a_matching_checkpoint() {
+    let dir = checkpointed_store("checkpoint_fast_path", 100);
+    assert!(Store::open_report(&dir).unwrap().checkpoint);
+    let store = Store::open(&dir).unwrap();
+    assert_contents(&store, 100, |_| 0);
+    assert!(store.dead_bytes() > 0);
+}
+
+#[test]
+fn open_replays_past_a_stale_checkpoint() {
+    let dir = checkpointed_store("checkpoint_stale", 100);
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(&key(7), &val(1, 7)).unwrap();
+    drop(store);
+
+    assert!(!Store::open_report(&dir).unwrap().checkpoint);
+    let store = Store::open(&dir).unwrap();
+    assert_contents(&store, 100, |i| (i == 7) as u32);
+    drop(store);
+
+    // A well-formed checkpoint of an older state is just as stale.
+    let path = format!("{dir}/INDEX");
+    let old = std::fs::read(&path).unwrap();
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(&key(8), &val(1, 8)).unwrap();
+    store.checkpoint().unwrap();
+    drop(store);
+    std::fs::write(&path, old).unwrap();
+    let store = Store::open(&dir).unwrap();
+    assert_contents(&store, 100, |i| (i == 7 || i == 8) as u32);
+}
+
+#[test]
+fn open_replays_past_a_corrupt_checkpoint() {
+    let dir = checkpointed_store("checkpoint_corrupt", 100);
+    let path = format!("{dir}/INDEX");
+    let mut bytes = std::fs::read(&path).unwrap();
+    let mid = bytes.len() / 2;
+    bytes[mid] ^= 0xff;
+    std::fs::write(&path, &bytes).unwrap();
+
+    assert!(!Store::open_report(&dir).unwrap().checkpoint);
+    let store = Store::open(&dir).unwrap();
+    assert_contents(&store, 100, |_| 0);
+
+    std::fs::write(&path, &bytes[..mid]).unwrap();
+    let store = Store::open(&dir).unwrap();
+    assert_contents(&store, 100, |_| 0);
+}
+
+/// A store of ten small inserts, closed; returns its data file.
+fn small_store(dir: &str, options: StoreOptions) -> String {
+    let mut store = Store::open_with(dir, options).unwrap();
+    for i in 0..10u8 {
+        store.insert(&[i], &[i; 4]).unwrap();
+    }
+    store.active_file_path().to_string_lossy().into_owned()
+}
+
+#[test]
+fn a_bad_op_at_the_end_is_a_torn_write_and_truncated() {
+    let dir = dir("torn_tail");
+    let path = small_store(&dir, StoreOptions::new());
+    let len = std::fs::metadata(&path).unwrap().len();
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.extend([0xee; 13]);
+    std::fs::write(&path, &bytes).unwrap();
+
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 10);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    store.insert(b"x", b"y").unwrap();
+    drop(store);
+    assert_eq!(Store::open(&dir).unwrap().len(), 11);
+
+    // A record cut short loses only itself.
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 10);
+    assert_eq!(store.get(b"x").unwrap(), None);
+}
+
+#[test]
+fn a_bad_op_before_valid_records_is_corruption() {
+    let dir = dir("corrupt_op");
+    let path = small_store(&dir, StoreOptions::new());
+    let mut bytes = std::fs::read(&path).unwrap();
+    let at = format::FILE_HEADER_LEN + 2 * format::insert_len(1, 4);
+    bytes[at + format::FIELD_LEN - 1] = 9;
+    std::fs::write(&path, &bytes).unwrap();
+
+    match Store::open(&dir) {
+        Err(kv::Error::Corrupt { offset, .. }) => assert_eq!(offset, at as u64),
+        Err(e) => panic!("{e:?}"),
+        Ok(_) => panic!("opened a corrupt store"),
+    }
+    // Nothing was cut off on the way.
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+}
+
+#[test]
+fn varint_files_tell_torn_writes_from_corruption() {
+    let options = || StoreOptions::new().varint_lengths(true);
+    let dir = dir("varint_recovery");
+    let path = small_store(&dir, options());
+    let len = std::fs::metadata(&path).unwrap().len();
+    let mut bytes = std::fs::read(&path).unwrap();
+    // An insert cut short, then a long run of bytes that chain up as
+    // empty removals but never line up with the end of the file.
+    bytes.extend([1, 1, 0x80, 0x80, 0xc0, 0x01, 7]);
+    for _ in 0..(1 << 19) {
+        bytes.extend([2, 0]);
+    }
+    bytes.push(0x80);
+    std::fs::write(&path, &bytes).unwrap();
+    let store = Store::open_with(&dir, options()).unwrap();
+    assert_eq!(store.len(), 10);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    drop(store);
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[format::FILE_HEADER_LEN + 2 * Encoding::Varint.insert_len(1, 4)] = 9;
+    std::fs::write(&path, &bytes).unwrap();
+    let opened = Store::open_with(&dir, options());
+    assert!(matches!(opened, Err(kv::Error::Corrupt { .. })));
+}
+
+/// Runs of each level as listed in `MANIFEST`: id, min key and max key.
+type Levels = Vec<Vec<(u64, Vec<u8>, Vec<u8>)>>;
+
+fn take(src: &mut &[u8], len: usize) -> Vec<u8> {
+    let (head, tail) = src.split_at(len);
+    *src = tail;
+    head.to_vec()
+}
+
+fn take_u64(src: &mut &[u8]) -> u64 {
+    u64::from_be_bytes(take(src, 8).try_into().unwrap())
+}
+
+/// `MANIFEST` split into the ids before the levels, the levels, and the
+/// options after them.
+fn read_manifest(dir: &str) -> (Vec<u8>, Levels, Vec<u8>) {
+    let bytes = std::fs::read(format!("{dir}/MANIFEST")).unwrap();
+    let mut src = &bytes[..];
+    let head = take(&mut src, 16);
+    let mut levels = Vec::new();
+    for _ in 0..take_u64(&mut src) {
+        let mut runs = Vec::new();
+        for _ in 0..take_u64(&mut src) {
+            let id = take_u64(&mut src);
+            let len = take_u64(&mut src) as usize;
+            let min = take(&mut src, len);
+            let len = take_u64(&mut src) as usize;
+            let max = take(&mut src, len);
+            runs.push((id, min, max));
+        }
+        levels.push(runs);
+    }
+    (head, levels, src.to_vec())
+}
+
+fn write_manifest(dir: &str, head: &[u8], levels: &Levels, tail: &[u8]) {
+    let mut bytes = head.to_vec();
+    let mut put = |val: &[u8]| bytes.extend_from_slice(val);
+    put(&(levels.len() as u64).to_be_bytes());
+    for runs in levels {
+        put(&(runs.len() as u64).to_be_bytes());
+        for (id, min, max) in runs {
+            put(&id.to_be_bytes());
+            put(&(min.len() as u64).to_be_bytes());
+            put(min);
+            put(&(max.len() as u64).to_be_bytes());
+            put(max);
+        }
+    }
+    bytes.extend_from_slice(tail);
+    std::fs::write(format!("{dir}/MANIFEST"), bytes).unwrap();
+}
+
+fn leveled() -> StoreOptions {
+    StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(4 * 1024)
+        .level_fanout(100)
+}
+
+#[test]
+fn overlapping_level0_runs_resolve_by_recency() {
+    let dir = dir("overlapping_level0");
+    let mut store = Store::open_with(&dir, leveled().level0_runs(10)).unwrap();
+    for round in 0..3 {
+        for i in 0..300 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.compact().unwrap();
+    }
+    let (_, levels, _) = read_manifest(&dir);
+    assert!(levels[0].len() > 1, "{levels:?}");
+    drop(store);
+
+    let store = Store::open_with(&dir, leveled()).unwrap();
+    for i in 0..300 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(2, i)));
+    }
+}
+
+#[test]
+fn overlapping_runs_below_level0_are_rejected() {
+    let dir = dir("overlapping_runs");
+    let mut store = Store::open_with(&dir, leveled().level0_runs(1)).unwrap();
+    for i in 0..2000 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    drop(store);
+    Store::open_with(&dir, leveled()).unwrap();
+
+    let (head, mut levels, tail) = read_manifest(&dir);
+    let runs = &mut levels[1];
+    assert!(runs.len() > 1, "{levels:?}");
+    runs[1].1 = runs[0].1.clone();
+    write_manifest(&dir, &head, &levels, &tail);
+    match Store::open_with(&dir, leveled()) {
+        Err(kv::Error::Unknown(message)) => {
+            assert!(message.starts_with("overlapping runs "), "{message}");
+            assert!(message.ends_with(" in level 1"), "{message}");
+        }
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("opened a store with overlapping runs"),
+    }
+}
+
+#[test]
+fn doctor_advises_compaction_after_overwrites() {
+    let dir = dir("doctor");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let report = store.doctor().unwrap();
+    assert_eq!(report.recommendation, "healthy");
+    assert_eq!((report.files, report.keys, report.dead_bytes), (1, 100, 0));
+    assert_eq!(report.corrupt, None);
+
+    for round in 1..10 {
+        for i in 0..100 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    let report = store.doctor().unwrap();
+    assert_eq!(report.recommendation, "compaction advised");
+    assert!(report.dead_ratio > 0.8, "{report:?}");
+    assert_eq!(report.dead_bytes, store.dead_bytes());
+
+    store.compact().unwrap();
+    let report = store.doctor().unwrap();
+    assert_eq!(report.recommendation, "healthy");
+    assert_eq!(report.dead_ratio, 0.0);
+}
+
+#[test]
+fn doctor_reports_where_a_file_stops_decoding() {
+    let dir = dir("doctor_corrupt");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..10 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let (_, location) = store.get_with_metadata(&key(5)).unwrap().unwrap();
+    let start = location.offset() - format::insert_len(6, 0) as u64;
+    let mut bytes = std::fs::read(store.active_file_path()).unwrap();
+    bytes[start as usize..][..8].copy_from_slice(&[0xEE; 8]);
+    std::fs::write(store.active_file_path(), bytes).unwrap();
+
+    let report = store.doctor().unwrap();
+    assert_eq!(report.corrupt, Some((location.file(), start)));
+    let id = location.file().get();
+    let expected = format!("corruption detected at file {id} offset {start}");
+    assert_eq!(report.recommendation, expected);
+}
+
+#[test]
+fn doctor_reports_a_key_repeated_in_a_sorted_run() {
+    let dir = dir("doctor_duplicate");
+    let mut store = Store::open_with(&dir, leveled().level0_runs(4)).unwrap();
+    for i in 0..20 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    // Runs may repeat keys across each other, just not within one.
+    store.insert(&key(19), &val(1, 19)).unwrap();
+    store.compact().unwrap();
+    assert_eq!(store.doctor().unwrap().recommendation, "healthy");
+    let (_, location) = store.get_with_metadata(&key(3)).unwrap().unwrap();
+    drop(store);
+
+    let run = format!("{dir}/{}.dat", location.file());
+    let mut bytes = std::fs::read(&run).unwrap();
+    let end = (location.offset() + location.length()) as usize;
+    let start = end - format::insert_len(6, location.length() as usize);
+    let record = bytes[start..end].to_vec();
+    bytes.splice(end..end, record);
+    std::fs::write(&run, bytes).unwrap();
+
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    let report = store.doctor().unwrap();
+    assert_eq!(report.duplicate, Some((location.file(), key(3))));
+    let expected = format!(
+        "duplicate key {} in sorted file {}",
+        yalskv::util::hex(&key(3)),
+        location.file().get()
+    );
+    assert_eq!(report.recommendation, expected);
+    assert_eq!(report.corrupt, None);
+}
+
+#[test]
+fn an_index_entry_past_its_file_reads_as_corruption() {
+    let dir = dir("entry_past_file");
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &[7; 100]).unwrap();
+    }
+    store.compact().unwrap();
+    let (_, location) = store.get_with_metadata(&key(99)).unwrap().unwrap();
+    let run = format!("{dir}/{}.dat", location.file());
+    let file = std::fs::OpenOptions::new().write(true).open(run).unwrap();
+    file.set_len(location.offset() + 10).unwrap();
+
+    let corrupt = |result: kv::Result<_>| match result {
+        Err(kv::Error::Corrupt { file, offset }) => {
+            assert_eq!((file, offset), (location.file().get(), location.offset()));
+        }
+        other => panic!("read past the end of the file: {other:?}"),
+    };
+    corrupt(store.get(&key(99)).map(drop));
+    corrupt(store.read_value(&key(99), Vec::new()).map(drop));
+    assert_eq!(store.get(&key(1)).unwrap(), Some(vec![7; 100]));
+}
+
+/// Swaps the offsets the checkpoint in `dir` records for its first two keys,
+/// leaving the checksum valid so the checkpoint still loads.
+fn swap_checkpointed_offsets(dir: &str) {
+    edit_checkpointed_entries(dir, |bytes, offsets| {
+        let (a, b) = (u64_at(bytes, offsets[0]), u64_at(bytes, offsets[1]));
+        bytes[offsets[0]..offsets[0] + 8].copy_from_slice(&b.to_be_bytes());
+        bytes[offsets[1]..offsets[1] + 8].copy_from_slice(&a.to_be_bytes());
+    });
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Calls `edit` with the body of the checkpoint in `dir` and where the
+/// offsets of its first two entries are, each followed by its length, then
+/// writes the body back under a fresh checksum.
+fn edit_checkpointed_entries(dir: &str, edit: impl FnOnce(&mut [u8], &[usize])) {
+    let path = format!("{dir}/INDEX");
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.truncate(bytes.len() - 4);
+    let files = u64_at(&bytes, 0) as usize;
+    let mut at = 8 + files * 24 + 16;
+    let mut offsets = Vec::new();
+    for _ in 0..2 {
+        at += 8 + u64_at(&bytes, at) as usize;
+        offsets.push(at + 8);
+        at += 24;
+    }
+    edit(&mut bytes, &offsets);
+    let crc = yalskv::util::crc32(&bytes);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+    std::fs::write(&path, &bytes).unwrap();
+}
+
+#[test]
+fn verify_on_read_catches_an_entry_pointing_at_another_key() {
+    let dir = dir("verify_on_read");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(&key(1), &val(0, 1)).unwrap();
+    store.insert(&key(2), &val(0, 2)).unwrap();
+    store.checkpoint().unwrap();
+    drop(store);
+    swap_checkpointed_offsets(&dir);
+
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(&key(1)).unwrap(), Some(val(0, 2)));
+    drop(store);
+
+    let store = Store::open_with(&dir, StoreOptions::new().verify_on_read(true)).unwrap();
+    let corrupt = |result: kv::Result<_>| match result {
+        Err(kv::Error::Corrupt { .. }) => {}
+        other => panic!("read another key's value: {other:?}"),
+    };
+    corrupt(store.get(&key(1)).map(drop));
+    corrupt(store.read_value(&key(2), Vec::new()).map(drop));
+}
+
+#[test]
+fn verify_on_read_accepts_every_record_layout() {
+    let dir = dir("verify_on_read_layouts");
+    let options = StoreOptions::new()
+        .verify_on_read(true)
+        .varint_lengths(true)
+        .timestamps(true);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    for round in 0..2 {
+        let pairs = (0..500).map(|i| (key(i), vec![round; i as usize]));
+        store.extend(pairs).unwrap();
+    }
+    store.remove_range(&key(0), &key(10)).unwrap();
+    for i in 10..500 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(vec![1; i as usize]));
+    }
+    let mut value = Vec::new();
+    assert!(store.read_value(&key(499), &mut value).unwrap());
+    assert_eq!(value, vec![1; 499]);
+}
+
+fn assert_missing(result: kv::Result<Store>, id: u64) {
+    match result {
+        Err(kv::Error::Corrupt { file, offset: 0 }) => assert_eq!(file, id),
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("opened without file {id}"),
+    }
+}
+
+fn assert_corrupt<T: std::fmt::Debug>(result: kv::Result<T>, id: u64) {
+    match result {
+        Err(kv::Error::Corrupt { file, offset: 0 }) => assert_eq!(file, id),
+        other => panic!("expected file {id} to be corrupt: {other:?}"),
+    }
+}
+
+#[test]
+fn a_missing_run_is_corruption_unless_skipped() {
+    let dir = dir("missing_run");
+    let options = || leveled().level0_runs(8);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    for i in 100..150 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    for i in 0..50 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    // Overwrites of its keys in a run that is then lost.
+    for i in 140..150 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.compact().unwrap();
+    let (_, location) = store.get_with_metadata(&key(140)).unwrap().unwrap();
+    drop(store);
+    let run = location.file();
+    std::fs::remove_file(format!("{dir}/{run}.dat")).unwrap();
+
+    assert_missing(Store::open_with(&dir, options()), run.get());
+    let mut store = Store::open_with(&dir, options().skip_missing_files(true)).unwrap();
+    assert_eq!(store.len(), 100);
+    assert_eq!(store.get(&key(10)).unwrap(), Some(val(0, 10)));
+    assert_eq!(store.get(&key(120)).unwrap(), Some(val(0, 120)));
+    // Older values of the keys the lost run overwrote do not show through.
+    for i in [140, 145, 149] {
+        assert_corrupt(store.get(&key(i)), run.get());
+    }
+    assert_corrupt(store.get_with_metadata(&key(141)), run.get());
+    assert_corrupt(store.read_value(&key(142), Vec::new()), run.get());
+    let scan: kv::Result<Vec<_>> = store.iter().collect();
+    assert_corrupt(scan, run.get());
+    // Until they are written again.
+    store.insert(&key(140), &val(2, 140)).unwrap();
+    store.remove(&key(141)).unwrap();
+    assert_eq!(store.get(&key(140)).unwrap(), Some(val(2, 140)));
+    assert_eq!(store.get(&key(141)).unwrap(), None);
+    assert_corrupt(store.get(&key(142)), run.get());
+    drop(store);
+    // Once skipped, the run is out of the manifest for good.
+    let store = Store::open_with(&dir, options()).unwrap();
+    assert_eq!(store.len(), 99);
+    assert_eq!(store.get(&key(142)).unwrap(), Some(val(0, 142)));
+}
+
+#[test]
+fn a_missing_active_log_is_corruption_unless_skipped() {
+    let dir = dir("missing_active");
+    let mut store = Store::open_with(&dir, leveled()).unwrap();
+    store.insert(&key(1), &val(0, 1)).unwrap();
+    store.compact().unwrap();
+    store.insert(&key(2), &val(0, 2)).unwrap();
+    drop(store);
+    let active = Store::open_report(&dir).unwrap().active.unwrap();
+    std::fs::remove_file(format!("{dir}/{active}.dat")).unwrap();
+
+    assert_missing(Store::open_with(&dir, leveled()), active.get());
+    let store = Store::open_with(&dir, leveled().skip_missing_files(true)).unwrap();
+    assert_eq!(store.get(&key(1)).unwrap(), Some(val(0, 1)));
+    assert_eq!(store.get(&key(2)).unwrap(), None);
+    drop(store);
+    Store::open_with(&dir, leveled()).unwrap();
+}
+
+#[test]
+fn a_replay_rebuilds_the_index_the_writes_built() {
+    let dir = dir("replay_matches_writes");
+    let options = || leveled().level0_runs(3);
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    for round in 0..8 {
+        for _ in 0..300 {
+            let i = rng.gen_range(0..400);
+            match rng.gen_range(0..10) {
+                0 | 1 => {
+                    store.remove(&key(i)).unwrap();
+                }
+                2 => {
+                    store
+                        .remove_range(&key(i), &key(i + rng.gen_range(1..20)))
+                        .unwrap();
+                }
+                _ => {
+                    let len = rng.gen_range(0..30);
+                    store.insert(&key(i), &vec![round; len]).unwrap();
+                }
+            }
+        }
+        if round < 7 {
+            store.compact().unwrap();
+        }
+    }
+    let keys: Vec<Vec<u8>> = store.keys().map(<[u8]>::to_vec).collect();
+    let written: Vec<_> = keys
+        .into_iter()
+        .map(|key| {
+            let found = store.get_with_metadata(&key).unwrap().unwrap();
+            (key, found)
+        })
+        .collect();
+    let dead_bytes = store.dead_bytes();
+    drop(store);
+
+    // Without a checkpoint every file is replayed: in parallel with the
+    // `rayon` feature, one by one without it.
+    std::fs::remove_file(format!("{dir}/INDEX")).unwrap();
+    let store = Store::open_with(&dir, options()).unwrap();
+    assert!(store.len() > 100, "{}", store.len());
+    assert_eq!(store.len(), written.len());
+    for (key, found) in &written {
+        assert_eq!(store.get_with_metadata(key).unwrap().as_ref(), Some(found));
+    }
+    assert_eq!(store.dead_bytes(), dead_bytes);
+}
+
+#[test]
+fn a_same_length_rewrite_of_the_tail_invalidates_the_checkpoint() {
+    let dir = dir("checkpoint_fingerprint");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), b"abc").unwrap();
+    }
+    store.checkpoint().unwrap();
+    let log = store.active_file_path().to_path_buf();
+    drop(store);
+    assert!(Store::open_report(&dir).unwrap().checkpoint);
+
+    // The last value, rewritten in place: no length changes.
+    let mut bytes = std::fs::read(&log).unwrap();
+    *bytes.last_mut().unwrap() = b'z';
+    std::fs::write(&log, &bytes).unwrap();
+    assert!(!Store::open_report(&dir).unwrap().checkpoint);
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(&key(99)).unwrap(), Some(b"abz".to_vec()));
+    assert_eq!(store.get(&key(98)).unwrap(), Some(b"abc".to_vec()));
+    store.checkpoint().unwrap();
+    drop(store);
+    assert!(Store::open_report(&dir).unwrap().checkpoint);
+
+    // An entry whose end overflows is no more trusted than one past the end.
+    edit_checkpointed_entries(&dir, |bytes, offsets| {
+        let length = offsets[0] + 8;
+        bytes[length..length + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+    });
+    assert!(!Store::open_report(&dir).unwrap().checkpoint);
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(&key(0)).unwrap(), Some(b"abc".to_vec()));
+    assert_eq!(store.get(&key(99)).unwrap(), Some(b"abz".to_vec()));
+    store.checkpoint().unwrap();
+    drop(store);
+    assert!(Store::open_report(&dir).unwrap().checkpoint);
+
+    // A checkpoint never stands in for a file that is gone.
+    std::fs::remove_file(&log).unwrap();
+    assert!(!Store::open_report(&dir).unwrap().checkpoint);
+    assert!(Store::open(&dir).is_err());
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::{dir, key, val};
+use yalskv::kv;
+use yalskv::sharded::{ShardHash, ShardedStore};
+
+fn populate(store: &mut ShardedStore, count: u32) {
+    for i in yalskv::util::mix((0..count).collect(), 3) {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+}
+
+fn assert_routed(store: &ShardedStore, count: u32) {
+    for i in 0..count {
+        let shard = store.shard_of(&key(i));
+        assert_eq!(store.shards()[shard].get(&key(i)).unwrap(), Some(val(0, i)));
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(0, i)));
+    }
+}
+
+#[test]
+fn keys_spread_over_shards_and_scan_in_order() {
+    let dir = dir("sharded");
+    let mut store = ShardedStore::open(&dir, 4, ShardHash::Crc32).unwrap();
+    populate(&mut store, 1000);
+    assert_eq!(store.len(), 1000);
+    let sizes: Vec<usize> = store.shards().iter().map(|shard| shard.len()).collect();
+    assert!(sizes.iter().all(|&size| size > 150), "{sizes:?}");
+    assert_routed(&store, 1000);
+
+    assert!(store.remove(&key(10)).unwrap());
+    assert!(!store.remove(&key(10)).unwrap());
+    let pairs: Vec<_> = store.iter().map(Result::unwrap).collect();
+    let expected: Vec<_> = (0..1000)
+        .filter(|&i| i != 10)
+        .map(|i| (key(i), val(0, i)))
+        .collect();
+    assert_eq!(pairs, expected);
+
+    store.insert(&key(10), &val(0, 10)).unwrap();
+    store.compact().unwrap();
+    drop(store);
+    let store = ShardedStore::open(&dir, 4, ShardHash::Crc32).unwrap();
+    assert_routed(&store, 1000);
+    assert_eq!(store.iter().count(), 1000);
+}
+
+#[test]
+fn reopening_needs_the_recorded_layout() {
+    let dir = dir("sharded_layout");
+    let mut store = ShardedStore::open(&dir, 3, ShardHash::Fnv1a).unwrap();
+    populate(&mut store, 100);
+    drop(store);
+    for (shards, hash) in [(4, ShardHash::Fnv1a), (3, ShardHash::Crc32)] {
+        match ShardedStore::open(&dir, shards, hash) {
+            Err(kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            Err(e) => panic!("unexpected error: {e:?}"),
+            Ok(_) => panic!("opened {shards} shards by {hash:?}"),
+        }
+    }
+    assert!(ShardedStore::open(&dir, 0, ShardHash::Fnv1a).is_err());
+    let store = ShardedStore::open(&dir, 3, ShardHash::Fnv1a).unwrap();
+    assert_routed(&store, 100);
+}
+
+fn by_length(key: &[u8]) -> u64 {
+    key.len() as u64
+}
+
+#[test]
+fn a_custom_hash_routes_keys_and_is_recorded_by_id() {
+    let dir = dir("sharded_custom");
+    let hash = ShardHash::Custom {
+        id: 7,
+        hash: by_length,
+    };
+    let mut store = ShardedStore::open(&dir, 4, hash).unwrap();
+    for len in 0..8 {
+        store.insert(&vec![1; len], &val(0, len as u32)).unwrap();
+        assert_eq!(store.shard_of(&vec![1; len]), len % 4);
+    }
+    drop(store);
+
+    let other = ShardHash::Custom {
+        id: 8,
+        hash: by_length,
+    };
+    for hash in [ShardHash::Crc32, other] {
+        match ShardedStore::open(&dir, 4, hash) {
+            Err(kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            Err(e) => panic!("unexpected error: {e:?}"),
+            Ok(_) => panic!("opened by {hash:?}"),
+        }
+    }
+    let store = ShardedStore::open(&dir, 4, hash).unwrap();
+    assert_eq!(store.len(), 8);
+    assert!(store.shards().iter().all(|shard| shard.len() == 2));
+    assert_eq!(store.get(&[1; 5]).unwrap(), Some(val(0, 5)));
+}
@@ -0,0 +1,763 @@
+mod common;
+
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::path::Path;
+
+use common::{dir, key, val};
+use yalskv::{format, kv, CompactionStrategy, FileId, RecordLocation, Store, StoreOptions};
+
+#[test]
+fn disk_record_count_includes_superseded_records_and_tombstones() {
+    let dir = dir("disk_record_count");
+    let mut store = Store::open(&dir).unwrap();
+    for round in 0..3 {
+        for i in 0..10 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+    }
+    store.remove(&key(0)).unwrap();
+    assert_eq!(store.len(), 9);
+    assert_eq!(store.disk_record_count().unwrap(), 31);
+
+    store.reduce(1 << 20).unwrap();
+    assert_eq!(store.disk_record_count().unwrap(), store.len());
+}
+
+#[test]
+fn empty_keys_are_stored_ranged_and_compacted() {
+    let dir = dir("empty_keys");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"", b"empty").unwrap();
+    store.insert(b"a", b"1").unwrap();
+    assert_eq!(store.get(b"").unwrap(), Some(b"empty".to_vec()));
+    assert_eq!(store.min_key(), Some(&b""[..]));
+
+    let range = store.range(Bound::Included(&b""[..]), Bound::Excluded(&b"b"[..]));
+    let keys: Vec<Vec<u8>> = range.map(|pair| pair.unwrap().0).collect();
+    assert_eq!(keys, vec![b"".to_vec(), b"a".to_vec()]);
+
+    store.reduce(1 << 20).unwrap();
+    assert_eq!(store.get(b"").unwrap(), Some(b"empty".to_vec()));
+    assert_eq!(store.len(), 2);
+
+    assert!(store.remove(b"").unwrap());
+    assert_eq!(store.get(b"").unwrap(), None);
+    store.reduce(1 << 20).unwrap();
+    assert_eq!(store.get(b"").unwrap(), None);
+    assert_eq!(store.len(), 1);
+    drop(store);
+
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.get(b"").unwrap(), None);
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn paths_point_into_the_opened_directory() {
+    let dir = dir("paths");
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.base_dir(), Path::new(&dir));
+    let active = store.active_file_path();
+    assert_eq!(active.parent(), Some(Path::new(&dir)));
+    assert!(active.exists());
+}
+
+#[test]
+fn retain_removes_the_entries_the_predicate_rejects() {
+    let dir = dir("retain");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let removed = store
+        .retain(|key, _| key.last().is_some_and(|digit| digit % 2 == 0))
+        .unwrap();
+    assert_eq!(removed, 50);
+    assert_eq!(store.len(), 50);
+    for i in 0..100 {
+        let expected = (i % 2 == 0).then(|| val(0, i));
+        assert_eq!(store.get(&key(i)).unwrap(), expected);
+    }
+    drop(store);
+
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 50);
+    assert!(store.keys().all(|key| key.last().unwrap() % 2 == 0));
+}
+
+fn disk_len(path: &Path) -> u64 {
+    std::fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn extend_buffers_the_pairs_and_flushes_once() {
+    let dir = dir("extend");
+    let options = StoreOptions::new().write_buffer_bytes(1 << 20);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let path = store.active_file_path();
+    let empty = disk_len(&path);
+
+    let pairs = (0..1000).map(|i| {
+        // Nothing reaches the file while the pairs are still coming in.
+        assert_eq!(disk_len(&path), empty);
+        (key(i), val(0, i))
+    });
+    store.extend(pairs).unwrap();
+    assert_eq!(disk_len(&path), store.active_file_bytes());
+    assert_eq!(store.len(), 1000);
+    assert!((0..1000).all(|i| store.get(&key(i)).unwrap() == Some(val(0, i))));
+}
+
+#[test]
+fn create_from_builds_a_store_but_keeps_existing_data() {
+    let dir = dir("create_from");
+    let pairs: Vec<_> = (0..100).map(|i| (key(i), val(0, i))).collect();
+    let store = Store::create_from(&dir, pairs.clone()).unwrap();
+    let read: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(read, pairs);
+    drop(store);
+
+    let again = vec![(key(0), val(1, 0))];
+    assert!(Store::create_from(&dir, again.clone()).is_err());
+    assert_eq!(Store::open(&dir).unwrap().len(), 100);
+
+    let options = StoreOptions::new().truncate(true);
+    let store = Store::create_from_with(&dir, options, again.clone()).unwrap();
+    let read: Vec<_> = store.iter().map(Result::unwrap).collect();
+    assert_eq!(read, again);
+}
+
+#[test]
+fn synced_writes_survive_without_a_clean_shutdown() {
+    let dir = dir("sync");
+    let options = StoreOptions::new().write_buffer_bytes(1 << 20);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    store.extend((0..100).map(|i| (key(i), val(0, i)))).unwrap();
+    store.sync().unwrap();
+    // Nothing new since the last sync: a no-op.
+    store.sync().unwrap();
+
+    let copy = common::dir("sync_copy");
+    common::copy_dir(&dir, &copy);
+    let recovered = Store::open(&copy).unwrap();
+    assert_eq!(recovered.len(), 100);
+    for i in 0..100 {
+        assert_eq!(recovered.get(&key(i)).unwrap(), Some(val(0, i)));
+    }
+    drop(store);
+}
+
+#[test]
+fn open_needs_the_directory_and_open_or_create_makes_it() {
+    let missing = format!("{}/missing", dir("open_missing"));
+    match Store::open(&missing) {
+        Err(kv::Error::IO(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("opened a missing directory"),
+    }
+    assert!(!Path::new(&missing).exists());
+
+    let nested = format!("{missing}/nested");
+    let mut store = Store::open_or_create(&nested).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    drop(store);
+    let store = Store::open(&nested).unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn removing_an_absent_key_writes_nothing() {
+    let dir = dir("remove_absent");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    let path = store.active_file_path();
+    let len = disk_len(&path);
+
+    assert!(!store.remove(b"never").unwrap());
+    assert_eq!(disk_len(&path), len);
+    assert_eq!(store.disk_record_count().unwrap(), 1);
+
+    assert!(store.remove(b"a").unwrap());
+    assert!(disk_len(&path) > len);
+    let len = disk_len(&path);
+    assert!(!store.remove(b"a").unwrap());
+    assert_eq!(disk_len(&path), len);
+}
+
+#[test]
+fn identical_writes_can_be_skipped() {
+    let dir = dir("skip_identical_writes");
+    let options = StoreOptions::new().skip_identical_writes(true);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"a", b"1").unwrap();
+    assert_eq!(store.disk_record_count().unwrap(), 1);
+    assert_eq!(store.dead_bytes(), 0);
+
+    store.insert(b"a", b"2").unwrap();
+    assert_eq!(store.disk_record_count().unwrap(), 2);
+    assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+
+    let dir = common::dir("identical_writes");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"a", b"1").unwrap();
+    assert_eq!(store.disk_record_count().unwrap(), 2);
+}
+
+#[test]
+fn file_ids_round_trip_and_format_as_file_stems() {
+    let id = FileId::new(42);
+    assert_eq!(id.get(), 42);
+    assert_eq!(id, FileId::new(42));
+    assert_eq!(id.to_string(), "00000000000000000042");
+    assert_eq!(format!("{id:?}"), "FileId(42)");
+    let ids: HashSet<FileId> = [id, FileId::new(42), FileId::new(u64::MAX)].into();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(FileId::new(u64::MAX).to_string().len(), 20);
+
+    let mut store = Store::open(&dir("file_id_stems")).unwrap();
+    store.insert(b"a", b"1").unwrap();
+    let id = store.get_with_metadata(b"a").unwrap().unwrap().1.file();
+    let stem = store.active_file_path().file_stem().unwrap().to_owned();
+    assert_eq!(stem.to_string_lossy(), id.to_string());
+}
+
+fn spread_store(name: &str) -> Store {
+    let options = StoreOptions::new().run_bytes(4 * 1024);
+    let mut store = Store::open_with(&dir(name), options).unwrap();
+    for round in 0..2 {
+        for i in (round * 200)..1000 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.reduce(4 * 1024).unwrap();
+    }
+    store
+}
+
+#[test]
+fn multi_get_returns_values_in_the_order_asked() {
+    let store = spread_store("multi_get");
+    let keys: Vec<Vec<u8>> = [999, 0, 1200, 450, 0, 100].map(key).into();
+    let keys: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+    let expected = vec![
+        Some(val(1, 999)),
+        Some(val(0, 0)),
+        None,
+        Some(val(1, 450)),
+        Some(val(0, 0)),
+        Some(val(0, 100)),
+    ];
+    assert_eq!(store.multi_get(&keys).unwrap(), expected);
+    assert!(store.multi_get(&[]).unwrap().is_empty());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_multi_get_matches_multi_get() {
+    let store = spread_store("par_multi_get");
+    let keys: Vec<Vec<u8>> = yalskv::util::mix((0..1100).collect(), 7)
+        .into_iter()
+        .map(key)
+        .collect();
+    let keys: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+    let serial = store.multi_get(&keys).unwrap();
+    assert_eq!(serial.iter().filter(|val| val.is_none()).count(), 100);
+    assert_eq!(store.par_multi_get(&keys).unwrap(), serial);
+}
+
+fn stored_bytes(dir: &str, location: RecordLocation) -> Vec<u8> {
+    let bytes = std::fs::read(format!("{dir}/{}.dat", location.file())).unwrap();
+    let start = location.offset() as usize;
+    bytes[start..start + location.length() as usize].to_vec()
+}
+
+#[test]
+fn get_with_metadata_points_at_the_stored_value() {
+    let dir = dir("get_with_metadata");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(b"a", b"first").unwrap();
+    store.insert(b"b", b"second").unwrap();
+    let (val, location) = store.get_with_metadata(b"b").unwrap().unwrap();
+    assert_eq!(val, b"second");
+    let stem = store.active_file_path().file_stem().unwrap().to_owned();
+    assert_eq!(location.file().to_string(), stem.to_string_lossy());
+    assert_eq!(location.length(), 6);
+    assert_eq!(stored_bytes(&dir, location), b"second");
+    assert_eq!(store.get_with_metadata(b"absent").unwrap(), None);
+
+    store.reduce(1 << 20).unwrap();
+    let (_, moved) = store.get_with_metadata(b"b").unwrap().unwrap();
+    assert_ne!(moved.file(), location.file());
+    assert_eq!(stored_bytes(&dir, moved), b"second");
+}
+
+#[test]
+fn record_locations_are_plain_values() {
+    let location = RecordLocation::new(FileId::new(3), 40, 5);
+    let copy = location;
+    assert_eq!(copy, location);
+    assert_eq!(location.file(), FileId::new(3));
+    assert_eq!(location.offset(), 40);
+    assert_eq!(location.length(), 5);
+    assert_ne!(location, RecordLocation::new(FileId::new(3), 40, 6));
+    assert_eq!(
+        format!("{location:?}"),
+        "RecordLocation { file: FileId(3), offset: 40, length: 5 }"
+    );
+}
+
+#[test]
+fn the_write_buffer_flushes_once_full() {
+    let dir = dir("write_buffer");
+    let record = format::insert_len(6, 4) as u64;
+    let options = StoreOptions::new().write_buffer_bytes(3 * record as usize);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let path = store.active_file_path();
+    let empty = disk_len(&path);
+
+    let pairs = (0..10u64).map(|i| {
+        // Of the `i` records so far, the ones in a full buffer are written.
+        assert_eq!(disk_len(&path), empty + i / 3 * 3 * record, "{i}");
+        (key(i as u32), format!("{i:04}").into_bytes())
+    });
+    store.extend(pairs).unwrap();
+    assert_eq!(disk_len(&path), empty + 10 * record);
+
+    store.insert(b"k99999", b"0000").unwrap();
+    assert_eq!(disk_len(&path), empty + 11 * record);
+}
+
+fn dir_listing(dir: &str) -> Vec<(String, u64)> {
+    let mut listing: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (name, entry.metadata().unwrap().len())
+        })
+        .collect();
+    listing.sort();
+    listing
+}
+
+#[test]
+fn open_report_describes_the_directory_without_changing_it() {
+    let dir = dir("open_report");
+    let report = Store::open_report(&dir).unwrap();
+    assert_eq!((report.files, report.bytes), (0, 0));
+    assert_eq!(report.active, None);
+    assert!(!report.checkpoint);
+
+    let options = || {
+        let options = StoreOptions::new().compaction(CompactionStrategy::Leveled);
+        options.run_bytes(2 * 1024).level0_runs(1)
+    };
+    let mut store = Store::open_with(&dir, options()).unwrap();
+    for i in 0..300 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    store.insert(b"last", b"1").unwrap();
+    let active = store.get_with_metadata(b"last").unwrap().unwrap().1.file();
+    let mut ids = common::file_ids(&mut store);
+    assert!(ids.len() > 1, "{ids:?}");
+    store.checkpoint().unwrap();
+    drop(store);
+
+    let listing = dir_listing(&dir);
+    let report = Store::open_report(&dir).unwrap();
+    assert_eq!(dir_listing(&dir), listing);
+    assert_eq!(report.files, ids.len());
+    let data = listing.iter().filter(|(name, _)| name.ends_with(".dat"));
+    assert_eq!(report.bytes, data.map(|(_, len)| len).sum::<u64>());
+    assert_eq!(report.active, Some(active));
+    assert!(report.checkpoint);
+
+    // A stray file is counted, though `open` ignores it.
+    ids.push(ids.last().unwrap() + 100);
+    let stray = format!("{dir}/{}.dat", FileId::new(*ids.last().unwrap()));
+    std::fs::write(stray, b"").unwrap();
+    let report = Store::open_report(&dir).unwrap();
+    assert_eq!(report.files, ids.len());
+    assert_eq!(report.active, Some(active));
+    assert_eq!(Store::open_with(&dir, options()).unwrap().len(), 301);
+}
+
+#[test]
+#[allow(deprecated)]
+fn get_and_lookup_agree() {
+    let mut store = spread_store("get_and_lookup");
+    store.remove(&key(3)).unwrap();
+    store.insert(b"", b"").unwrap();
+    let keys: Vec<Vec<u8>> = [0, 3, 250, 999, 1000].map(key).into();
+    for key in keys.iter().map(Vec::as_slice).chain([&b""[..], b"absent"]) {
+        let got = store.get(key).unwrap();
+        assert_eq!(store.lookup(key).unwrap(), got);
+    }
+    assert_eq!(store.get(&key(250)).unwrap(), Some(val(1, 250)));
+    assert_eq!(store.get(&key(3)).unwrap(), None);
+}
+
+#[test]
+fn iter_raw_yields_every_record_written() {
+    let dir = dir("iter_raw");
+    let mut store = Store::open(&dir).unwrap();
+    let round = |store: &mut Store, round: u32| {
+        for i in 0..50 {
+            store.insert(&key(i), &val(round, i)).unwrap();
+        }
+        store.remove(&key(round)).unwrap();
+    };
+    for n in 0..3 {
+        round(&mut store, n);
+    }
+    let records: Vec<_> = store.iter_raw().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 3 * 51);
+    assert_eq!(records.iter().filter(|(_, _, r)| r.is_remove()).count(), 3);
+
+    // The 49 live keys are compacted, and the next round follows them.
+    store.reduce(1 << 20).unwrap();
+    round(&mut store, 3);
+    let records: Vec<_> = store.iter_raw().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 49 + 51);
+    let ids: Vec<FileId> = records.iter().map(|(id, _, _)| *id).collect();
+    assert!(ids.windows(2).all(|pair| pair[0] <= pair[1]));
+    for pair in records.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            assert!(pair[0].1 < pair[1].1);
+        }
+    }
+    let (_, offset, last) = records.last().unwrap();
+    assert_eq!(last, &yalskv::Record::Remove(key(3)));
+    let len = last.encoded_len(store.file().encoding()) as u64;
+    assert_eq!(offset + len, store.active_file_bytes());
+}
+
+#[test]
+fn read_value_streams_a_large_value() {
+    let dir = dir("read_value");
+    let mut store = Store::open(&dir).unwrap();
+    let large: Vec<u8> = (0..5 << 20).map(|i: u32| (i % 251) as u8).collect();
+    store.insert(b"large", &large).unwrap();
+    store.insert(b"small", b"1").unwrap();
+
+    let mut out = Vec::new();
+    assert!(store.read_value(b"large", &mut out).unwrap());
+    assert!(out == large);
+    out.clear();
+    assert!(store.read_value(b"small", &mut out).unwrap());
+    assert_eq!(out, b"1");
+    out.clear();
+    assert!(!store.read_value(b"absent", &mut out).unwrap());
+    assert!(out.is_empty());
+
+    store.reduce(1 << 20).unwrap();
+    let mut file = std::fs::File::create(format!("{dir}/large.out")).unwrap();
+    assert!(store.read_value(b"large", &mut file).unwrap());
+    drop(file);
+    assert!(std::fs::read(format!("{dir}/large.out")).unwrap() == large);
+}
+
+#[test]
+fn flushing_a_clean_store_writes_nothing() {
+    let dir = dir("clean_flush");
+    let options = StoreOptions::new().write_buffer_bytes(1 << 20);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    store.extend((0..10).map(|i| (key(i), val(0, i)))).unwrap();
+    let path = store.active_file_path();
+    assert_eq!(disk_len(&path), store.active_file_bytes());
+
+    // Whatever a second flush or sync wrote would show up past the cut.
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(0).unwrap();
+    store.flush().unwrap();
+    store.sync().unwrap();
+    store.flush().unwrap();
+    assert_eq!(disk_len(&path), 0);
+
+    store.insert(&key(10), &val(0, 10)).unwrap();
+    assert_eq!(disk_len(&path), store.active_file_bytes());
+}
+
+#[test]
+fn a_hinted_bulk_load_is_written_whole_and_trimmed() {
+    let dir = dir("hint_load");
+    let options = StoreOptions::new().write_buffer_bytes(4 * 1024);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let path = store.active_file_path();
+    let empty = disk_len(&path);
+
+    let bytes = (0..2000)
+        .map(|i| key(i).len() + val(0, i).len())
+        .sum::<usize>();
+    store.hint_load(2000, bytes as u64);
+    let pairs = (0..2000).map(|i| {
+        assert_eq!(disk_len(&path), empty, "{i}");
+        (key(i), val(0, i))
+    });
+    store.extend(pairs).unwrap();
+    assert_eq!(disk_len(&path), store.active_file_bytes());
+    drop(store);
+
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 2000);
+    assert_eq!(store.get(&key(1999)).unwrap(), Some(val(0, 1999)));
+    // Absurd hints are capped rather than allocated.
+    store.hint_load(usize::MAX, u64::MAX);
+    store.insert(b"after", b"1").unwrap();
+    assert_eq!(disk_len(&path), store.active_file_bytes());
+}
+
+fn listing_names(dir: &str) -> Vec<String> {
+    dir_listing(dir).into_iter().map(|(name, _)| name).collect()
+}
+
+#[test]
+fn custom_file_names_round_trip_and_share_a_directory() {
+    let dir = dir("file_names");
+    let custom = || StoreOptions::new().file_prefix("log-").extension("seg");
+    let mut store = Store::open_with(&dir, custom()).unwrap();
+    let mut plain = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+        plain.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.reduce(1 << 20).unwrap();
+    store.insert(b"last", b"1").unwrap();
+    let segment = format!(
+        "log-{}.seg",
+        store.get_with_metadata(b"last").unwrap().unwrap().1.file()
+    );
+    assert_eq!(store.active_file_path(), Path::new(&dir).join(&segment));
+    drop((store, plain));
+
+    let names = listing_names(&dir);
+    assert!(names.contains(&segment), "{names:?}");
+    assert!(names.contains(&"log-MANIFEST".to_string()), "{names:?}");
+    assert!(names.contains(&"log-INDEX".to_string()), "{names:?}");
+    let custom_names = names.iter().filter(|name| name.starts_with("log-"));
+    assert!(
+        custom_names.clone().all(|name| !name.ends_with(".dat")),
+        "{names:?}"
+    );
+    assert_eq!(custom_names.count(), 3, "{names:?}");
+
+    let store = Store::open_with(&dir, custom()).unwrap();
+    assert_eq!(store.len(), 101);
+    assert_eq!(store.get(&key(7)).unwrap(), Some(val(0, 7)));
+    let plain = Store::open(&dir).unwrap();
+    assert_eq!(plain.len(), 100);
+    assert_eq!(plain.get(&key(7)).unwrap(), Some(val(1, 7)));
+    assert_eq!(Store::open_report_with(&dir, &custom()).unwrap().files, 1);
+
+    for reserved in ["tmp", "reduce", ""] {
+        let options = StoreOptions::new().extension(reserved);
+        assert!(Store::open_with(&dir, options).is_err(), "{reserved}");
+    }
+    assert!(Store::open_with(&dir, StoreOptions::new().file_prefix("a/b")).is_err());
+}
+
+#[test]
+fn prefixes_differing_after_a_dot_do_not_collide() {
+    let dir = dir("dotted_prefixes");
+    let mut a = Store::open_with(&dir, StoreOptions::new().file_prefix("s.1")).unwrap();
+    let mut b = Store::open_with(&dir, StoreOptions::new().file_prefix("s.2")).unwrap();
+    std::thread::scope(|scope| {
+        for (store, val) in [(&mut a, b"a"), (&mut b, b"b")] {
+            scope.spawn(move || {
+                for i in 0..200 {
+                    store.insert(&key(i), val).unwrap();
+                    store.checkpoint().unwrap();
+                    store.compact().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(a.len(), 200);
+    assert_eq!(b.get(&key(7)).unwrap(), Some(b"b".to_vec()));
+    drop((a, b));
+    let a = Store::open_with(&dir, StoreOptions::new().file_prefix("s.1")).unwrap();
+    assert_eq!(a.get(&key(7)).unwrap(), Some(b"a".to_vec()));
+}
+
+#[test]
+fn len_tracks_live_keys_through_every_write() {
+    let dir = dir("len");
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!((store.len(), store.is_empty()), (0, true));
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    assert_eq!(store.len(), 100);
+    store.insert(&key(5), &val(1, 5)).unwrap();
+    assert_eq!(store.len(), 100);
+    store.remove(&key(5)).unwrap();
+    store.remove(&key(5)).unwrap();
+    assert_eq!(store.len(), 99);
+    assert_eq!(store.remove_range(&key(10), &key(20)).unwrap(), 10);
+    assert_eq!(store.len(), 89);
+    store.insert(&key(15), &val(1, 15)).unwrap();
+    assert_eq!(store.len(), 90);
+    store.retain(|key, _| key != common::key(99)).unwrap();
+    assert_eq!(store.len(), 89);
+
+    store.compact().unwrap();
+    assert_eq!(store.len(), 89);
+    assert_eq!(store.len(), store.iter().count());
+    drop(store);
+    let store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 89);
+    assert!(!store.is_empty());
+}
+
+#[test]
+fn active_file_bytes_counts_the_header_and_buffered_writes() {
+    let dir = dir("active_file_bytes");
+    let options = StoreOptions::new().write_buffer_bytes(1 << 20);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    let header = format::FILE_HEADER_LEN as u64;
+    assert_eq!(store.active_file_bytes(), header);
+
+    store.insert(b"key", b"value").unwrap();
+    let insert = format::insert_len(3, 5) as u64;
+    assert_eq!(store.active_file_bytes(), header + insert);
+    store.remove(b"key").unwrap();
+    let written = header + insert + format::remove_len(3) as u64;
+    assert_eq!(store.active_file_bytes(), written);
+    assert_eq!(disk_len(&store.active_file_path()), written);
+
+    // Appended straight to the file, the record stays in its buffer.
+    let record = yalskv::Record::Insert(key(0), b"1234".to_vec());
+    store.file().append_record(&record).unwrap();
+    let buffered = format::insert_len(6, 4) as u64;
+    assert_eq!(store.active_file_bytes(), written + buffered);
+    assert_eq!(disk_len(&store.active_file_path()), written);
+    store.flush().unwrap();
+    assert_eq!(disk_len(&store.active_file_path()), written + buffered);
+}
+
+#[test]
+fn sync_range_makes_the_range_durable() {
+    let dir = dir("sync_range");
+    let options = StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .run_bytes(2 * 1024)
+        .level0_runs(1);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    for i in 0..200 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    store.compact().unwrap();
+    for i in 100..150 {
+        store.insert(&key(i), &val(1, i)).unwrap();
+    }
+    store.remove_range(&key(120), &key(130)).unwrap();
+
+    let (start, end) = (key(110), key(140));
+    store
+        .sync_range(Bound::Included(&start[..]), Bound::Excluded(&end[..]))
+        .unwrap();
+    store
+        .sync_range(Bound::Included(&end[..]), Bound::Excluded(&start[..]))
+        .unwrap();
+    store
+        .sync_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+
+    let copy = common::dir("sync_range_copy");
+    common::copy_dir(&dir, &copy);
+    let recovered = Store::open(&copy).unwrap();
+    for i in 110..140 {
+        let expected = (!(120..130).contains(&i)).then(|| val(1, i));
+        assert_eq!(recovered.get(&key(i)).unwrap(), expected, "{i}");
+    }
+    drop(store);
+}
+
+#[test]
+fn open_removes_what_an_interrupted_compaction_left_behind() {
+    let dir = dir("leftovers");
+    let mut store = Store::open(&dir).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let active = store.active_file_path().to_path_buf();
+    drop(store);
+    let id = active.file_stem().unwrap().to_string_lossy().into_owned();
+
+    std::fs::create_dir(format!("{dir}/{id}")).unwrap();
+    std::fs::write(format!("{dir}/{id}/{:020}.dat", 0), b"junk").unwrap();
+    std::fs::write(format!("{dir}/{:020}.reduce", 9), b"junk").unwrap();
+    std::fs::write(format!("{dir}/{id}.tmp"), b"junk").unwrap();
+    std::fs::write(format!("{dir}/notes.txt"), b"keep").unwrap();
+    std::fs::create_dir(format!("{dir}/backup")).unwrap();
+
+    let mut store = Store::open(&dir).unwrap();
+    assert_eq!(store.len(), 100);
+    let expected = [
+        format!("{id}.dat"),
+        "MANIFEST".into(),
+        "backup".into(),
+        "notes.txt".into(),
+    ];
+    assert_eq!(listing_names(&dir), expected);
+    store.reduce(4 * 1024).unwrap();
+    for i in 0..100 {
+        assert_eq!(store.get(&key(i)).unwrap(), Some(val(0, i)));
+    }
+}
+
+#[test]
+fn file_stats_split_each_file_into_live_and_dead() {
+    let dir = dir("file_stats");
+    let options = StoreOptions::new()
+        .compaction(CompactionStrategy::Leveled)
+        .level0_runs(8);
+    let mut store = Store::open_with(&dir, options).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), b"old").unwrap();
+    }
+    store.compact().unwrap();
+    for i in 0..90 {
+        store.insert(&key(i), b"new").unwrap();
+    }
+    store.remove(&key(99)).unwrap();
+
+    let stats = store.file_stats().unwrap();
+    assert_eq!(stats.len(), 2, "{stats:?}");
+    let (run, log) = (&stats[0], &stats[1]);
+    assert!(run.file_id < log.file_id);
+    assert_eq!((run.total_records, run.live_records), (100, 9));
+    // Every record in the run has the same size.
+    assert_eq!(run.dead_bytes * 100, run.total_bytes * 91);
+    // Only the tombstone in the log is dead.
+    assert_eq!((log.total_records, log.live_records), (91, 90));
+    assert!(log.dead_bytes > 0 && log.dead_bytes < log.total_bytes / 50);
+}
+
+#[test]
+fn read_only_lookups_share_the_store() {
+    let dir = dir("shared_lookups");
+    let mut store = Store::open_with(&dir, StoreOptions::new().timestamps(true)).unwrap();
+    for i in 0..100 {
+        store.insert(&key(i), &val(0, i)).unwrap();
+    }
+    let store = &store;
+    std::thread::scope(|scope| {
+        for t in 0..4 {
+            scope.spawn(move || {
+                for i in (t..100).step_by(4) {
+                    let (value, _) = store.get_with_metadata(&key(i)).unwrap().unwrap();
+                    assert_eq!(value, val(0, i));
+                    let (value, _) = store.lookup_with_meta(&key(i)).unwrap().unwrap();
+                    assert_eq!(value, val(0, i));
+                    let mut out = Vec::new();
+                    assert!(store.read_value(&key(i), &mut out).unwrap());
+                    assert_eq!(out, val(0, i));
+                }
+            });
+        }
+    });
+}
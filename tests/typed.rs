@@ -0,0 +1,75 @@
+mod common;
+
+use std::ops::Range;
+
+use common::dir;
+use yalskv::typed::U64KeyStore;
+use yalskv::{kv, Store};
+
+fn ids(store: &U64KeyStore, range: Range<u64>) -> Vec<u64> {
+    let entries = store.range(range).map(Result::unwrap);
+    entries
+        .map(|(id, val)| {
+            assert_eq!(val, id.to_le_bytes());
+            id
+        })
+        .collect()
+}
+
+#[test]
+fn ids_range_in_numeric_order() {
+    let dir = dir("u64_keys");
+    let mut store = U64KeyStore::new(Store::open(&dir).unwrap());
+    for id in (1..1000u64).rev() {
+        store.insert(id, &id.to_le_bytes()).unwrap();
+    }
+    let big = 1 << 40;
+    store.insert(big, &big.to_le_bytes()).unwrap();
+
+    assert_eq!(ids(&store, 100..200), (100..200).collect::<Vec<_>>());
+    assert_eq!(
+        ids(&store, 990..u64::MAX),
+        [990, 991, 992, 993, 994, 995, 996, 997, 998, 999, big]
+    );
+    assert_eq!(
+        ids(
+            &store,
+            Range {
+                start: 200,
+                end: 100
+            }
+        ),
+        []
+    );
+    assert_eq!(ids(&store, 5..5), []);
+
+    assert!(store.remove(150).unwrap());
+    assert!(!store.remove(150).unwrap());
+    assert_eq!(store.get(150).unwrap(), None);
+    assert_eq!(store.get(151).unwrap(), Some(151u64.to_le_bytes().to_vec()));
+    assert_eq!(store.range(100..200).count(), 99);
+
+    drop(store);
+    let store = U64KeyStore::new(Store::open(&dir).unwrap());
+    assert_eq!(store.store().len(), 999);
+    assert_eq!(store.get(big).unwrap(), Some(big.to_le_bytes().to_vec()));
+}
+
+#[test]
+fn a_key_of_another_length_does_not_decode() {
+    let dir = dir("u64_foreign_key");
+    let mut store = Store::open(&dir).unwrap();
+    store.insert(&[0, 0, 0, 0, 0, 0, 0, 1, 0], b"x").unwrap();
+    let mut store = U64KeyStore::new(store);
+    store.insert(2, b"y").unwrap();
+    let entries: Vec<_> = store.range(0..10).collect();
+    assert_eq!(entries.len(), 2);
+    match &entries[0] {
+        Err(kv::Error::Unknown(message)) => {
+            assert!(message.contains("000000000000000100"), "{message}")
+        }
+        other => panic!("unexpected entry: {other:?}"),
+    }
+    assert_eq!(entries[1].as_ref().unwrap(), &(2, b"y".to_vec()));
+    assert_eq!(store.into_inner().len(), 2);
+}
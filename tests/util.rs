@@ -0,0 +1,118 @@
+mod common;
+
+use common::{dir, key};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use yalskv::util::{self, SizeHistogram};
+use yalskv::{Store, StoreOptions};
+
+fn check_size_histogram(dir: &str, options: StoreOptions) {
+    let mut store = Store::open_with(dir, options).unwrap();
+    for i in 0..30 {
+        store.insert(&key(i), &[0; 10]).unwrap();
+    }
+    for i in 30..40 {
+        store.insert(&key(i), &[0; 1000]).unwrap();
+    }
+    store.insert(b"", b"").unwrap();
+    // Superseded and removed values are not counted.
+    store.insert(&key(0), &[0; 12]).unwrap();
+    store.insert(b"gone", &[0; 5000]).unwrap();
+    store.remove(b"gone").unwrap();
+
+    let histogram = util::size_histogram(&store);
+    let (ten, thousand) = (SizeHistogram::bucket(10), SizeHistogram::bucket(1000));
+    assert_eq!((ten, thousand), (4, 10));
+    let mut vals = vec![0; thousand + 1];
+    (vals[0], vals[ten], vals[thousand]) = (1, 30, 10);
+    assert_eq!(histogram.vals, vals);
+    let mut keys = vec![0; SizeHistogram::bucket(6) + 1];
+    (keys[0], keys[SizeHistogram::bucket(6)]) = (1, 40);
+    assert_eq!(histogram.keys, keys);
+}
+
+#[test]
+fn size_histogram_buckets_live_lengths() {
+    check_size_histogram(&dir("size_histogram"), StoreOptions::new());
+}
+
+#[test]
+fn size_histogram_leaves_out_timestamps() {
+    let options = StoreOptions::new().timestamps(true);
+    check_size_histogram(&dir("size_histogram_timestamps"), options);
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn size_histogram_leaves_out_encryption_overhead() {
+    let options = StoreOptions::new()
+        .timestamps(true)
+        .encryption_key(yalskv::EncryptionKey([7; 32]));
+    check_size_histogram(&dir("size_histogram_encrypted"), options);
+}
+
+#[test]
+fn data_iter_yields_what_data_collects() {
+    let pairs: Vec<_> = util::data_iter(100, 7).collect();
+    assert_eq!(pairs.len(), 100);
+    assert_eq!(pairs, util::data(100, 7));
+    assert_ne!(pairs, util::data(100, 8));
+    // Lazy: a prefix of a huge stream costs only the prefix.
+    let prefix: Vec<_> = util::data_iter(usize::MAX, 7).take(10).collect();
+    assert_eq!(prefix, pairs[..10]);
+}
+
+#[test]
+fn crc32_matches_known_vectors() {
+    assert_eq!(util::crc32(b""), 0);
+    assert_eq!(util::crc32(b"123456789"), 0xCBF4_3926);
+    let fox = b"The quick brown fox jumps over the lazy dog";
+    assert_eq!(util::crc32(fox), 0x414F_A339);
+
+    let mut crc = util::Crc32::new();
+    for chunk in fox.chunks(5) {
+        crc.update(chunk);
+    }
+    assert_eq!(crc.finish(), util::crc32(fox));
+}
+
+#[test]
+fn mixing_with_one_rng_state_gives_aligned_permutations() {
+    let keys: Vec<u32> = (0..100).collect();
+    let vals: Vec<String> = keys.iter().map(|i| format!("v{i}")).collect();
+    let rng = StdRng::seed_from_u64(11);
+    let keys = util::mix_with(keys, &mut rng.clone());
+    let vals = util::mix_with(vals, &mut rng.clone());
+    assert_ne!(keys, (0..100).collect::<Vec<_>>());
+    assert!(keys.iter().zip(vals).all(|(i, val)| val == format!("v{i}")));
+
+    assert_eq!(util::mix((0..100).collect::<Vec<u32>>(), 11), keys);
+    let mut rng = StdRng::seed_from_u64(11);
+    let first = util::mix_with((0..100).collect::<Vec<u32>>(), &mut rng);
+    let second = util::mix_with((0..100).collect::<Vec<u32>>(), &mut rng);
+    assert_eq!(first, keys);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn hex_writes_two_digits_per_byte() {
+    let bytes = [0x00, 0xab, 0x1f, 0xff];
+    assert_eq!(util::hex(&bytes), "00ab1fff");
+    assert_eq!(util::hex_upper(&bytes), "00AB1FFF");
+    assert_eq!(util::hex(&[]), "");
+
+    let mut line = String::from("key=");
+    util::write_hex(&mut line, &[0xde, 0xad]).unwrap();
+    line.push_str(" val=");
+    util::write_hex_upper(&mut line, &[0xbe, 0xef]).unwrap();
+    assert_eq!(line, "key=dead val=BEEF");
+
+    let all: Vec<u8> = (0..=255).collect();
+    let hex = util::hex(&all);
+    let decoded: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+        .collect();
+    assert_eq!(decoded, all);
+    assert_eq!(util::hex_upper(&all), hex.to_uppercase());
+}